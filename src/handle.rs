@@ -1,54 +1,222 @@
-use schemars::JsonSchema;
-use serde::Serialize;
-
 use crate::{
-    msg::{HandleAnswer, HandleMsg, RemoveItemData, ResponseStatus, StaticItemData},
+    msg::{
+        AddItemData, Category, ContractStatus, HandleAnswer, HandleMsg, RemoveItemData,
+        ResponseStatus, SellerProfile, StaticItemData, UserContactData,
+    },
+    return_logic::{
+        approve_return, deposit_seller_bond, reject_return, request_return, resolve_return_dispute,
+        withdraw_seller_bond,
+    },
     state::{
-        get_all_participating_users_addresses, get_category_prefixes,
+        get_all_participating_users_addresses, get_category_item_by_url,
+        get_category_item_group_size, get_category_prefixes, load_categories, load_config,
+        may_load_category, may_load_order, may_load_seller_profile, order_key,
         remove_all_category_item_users_details, remove_category_item, remove_current_group_size,
-        remove_user_item_quantity, save_new_item, update_current_group_size,
+        remove_order_participants_contact_data, remove_seller_listing, remove_user_item_quantity,
+        save_block_time, save_categories, save_config, save_contact_profile, save_new_item,
+        save_order, save_seller_listing, save_seller_profile, update_current_group_size,
+        update_stats, Config, ListingRef, PREFIX_REVOKED_PERMITS,
     },
-    update_logic::update_user_for_item,
-    viewing_key::ViewingKey,
+    update_logic::{emergency_withdraw, update_user_for_item},
+    utils::BLOCK_SIZE,
+    viewing_key::{ViewingKey, ViewingKeyScope},
 };
 use cosmwasm_std::{
-    to_binary, Api, Env, Extern, HandleResponse, Querier, StdError, StdResult, Storage,
+    to_binary, Api, Binary, Env, Extern, HandleResponse, HumanAddr, Querier, StdError, StdResult,
+    Storage, Uint128,
 };
-use secret_toolkit::crypto::sha_256;
-
-#[derive(Serialize, Debug, JsonSchema)]
-#[serde(rename_all = "snake_case")]
-pub enum HandleResp {
-    Success,
-    ViewingKey(String),
-}
+use secret_toolkit::{crypto::sha_256, permit::RevokedPermits, utils::pad_handle_result};
 
 pub fn handle<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     msg: HandleMsg,
 ) -> StdResult<HandleResponse> {
-    match msg {
-        HandleMsg::AddItem(static_item_data) => add_new_item(deps, static_item_data),
+    save_block_time(&mut deps.storage, env.block.time)?;
+    if let Err(err) = check_contract_status(deps, &msg) {
+        return pad_handle_result(Err(err), BLOCK_SIZE);
+    }
+
+    let response = match msg {
+        HandleMsg::AddItem(add_item_data) => add_new_item(deps, env, add_item_data),
         HandleMsg::UpdateItem(update_item_data) => {
             update_user_for_item(deps, env, update_item_data)
         }
         HandleMsg::RemoveItem(remove_item_data) => remove_item(deps, env, remove_item_data),
-        HandleMsg::SetViewingKey { key, .. } => set_viewing_key(deps, env, key),
+        HandleMsg::SetViewingKey {
+            key,
+            expires_at,
+            scopes,
+            ..
+        } => set_viewing_key(deps, env, key, expires_at, scopes),
+        HandleMsg::CreateViewingKey {
+            entropy,
+            expires_at,
+            scopes,
+            ..
+        } => create_viewing_key(deps, env, entropy, expires_at, scopes),
+        HandleMsg::SetContactProfile {
+            email,
+            delivery_address,
+            ..
+        } => set_contact_profile(deps, env, email, delivery_address),
+        HandleMsg::RegisterSeller {
+            display_name,
+            contact_email,
+            payout_address,
+            public_key,
+            ..
+        } => register_seller(
+            deps,
+            env,
+            display_name,
+            contact_email,
+            payout_address,
+            public_key,
+        ),
+        HandleMsg::SetSellerVerified {
+            seller, verified, ..
+        } => set_seller_verified(deps, env, seller, verified),
+        HandleMsg::MarkOrderShipped { category, url, .. } => {
+            mark_order_shipped(deps, env, category, url)
+        }
+        HandleMsg::DepositSellerBond { .. } => deposit_seller_bond(deps, env),
+        HandleMsg::WithdrawSellerBond { amount, .. } => withdraw_seller_bond(deps, env, amount),
+        HandleMsg::RequestReturn {
+            category,
+            url,
+            quantity,
+            reason,
+            ..
+        } => request_return(deps, env, category, url, quantity, reason),
+        HandleMsg::ApproveReturn {
+            category,
+            url,
+            buyer,
+            ..
+        } => approve_return(deps, env, category, url, buyer),
+        HandleMsg::RejectReturn {
+            category,
+            url,
+            buyer,
+            ..
+        } => reject_return(deps, env, category, url, buyer),
+        HandleMsg::ResolveReturnDispute {
+            category,
+            url,
+            buyer,
+            refund,
+            ..
+        } => resolve_return_dispute(deps, env, category, url, buyer, refund),
+        HandleMsg::RevokePermit { permit_name, .. } => revoke_permit(deps, env, permit_name),
+        HandleMsg::AddCategory {
+            key, name, parent, ..
+        } => add_category(deps, env, key, name, parent),
+        HandleMsg::RenameCategory { key, name, .. } => rename_category(deps, env, key, name),
+        HandleMsg::DisableCategory { key, .. } => disable_category(deps, env, key),
+        HandleMsg::ChangeAdmin { address, .. } => change_admin(deps, env, address),
+        HandleMsg::AcceptAdmin { .. } => accept_admin(deps, env),
+        HandleMsg::SetContractStatus { status, .. } => set_contract_status(deps, env, status),
+        HandleMsg::EmergencyWithdraw { category, url, .. } => {
+            emergency_withdraw(deps, env, category, url)
+        }
+    };
+    pad_handle_result(response, BLOCK_SIZE)
+}
+
+fn check_contract_status<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    msg: &HandleMsg,
+) -> StdResult<()> {
+    match load_config(&deps.storage)?.status {
+        ContractStatus::Normal => Ok(()),
+        // Joins are checked when the quantity is updated, as reductions are still allowed
+        ContractStatus::StopNewListingsAndJoins => match msg {
+            HandleMsg::AddItem(_) => Err(StdError::generic_err("Adding items is paused")),
+            _ => Ok(()),
+        },
+        ContractStatus::StopAll => match msg {
+            HandleMsg::SetContractStatus { .. }
+            | HandleMsg::ChangeAdmin { .. }
+            | HandleMsg::AcceptAdmin { .. }
+            | HandleMsg::EmergencyWithdraw { .. } => Ok(()),
+            _ => Err(StdError::generic_err(
+                "The contract is stopped. Only emergency withdrawals are allowed",
+            )),
+        },
     }
 }
 
 fn add_new_item<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
-    static_item_data: StaticItemData,
+    env: Env,
+    add_item_data: AddItemData,
 ) -> StdResult<HandleResponse> {
+    let seller_canonical_address = deps.api.canonical_address(&env.message.sender)?;
+    if may_load_seller_profile(&deps.storage, &seller_canonical_address)?.is_none() {
+        return Err(StdError::generic_err(
+            "Only registered sellers can add items. Use RegisterSeller first",
+        ));
+    }
+    let static_item_data = StaticItemData {
+        name: add_item_data.name,
+        category: add_item_data.category,
+        url: add_item_data.url,
+        img_url: add_item_data.img_url,
+        seller_address: env.message.sender.to_string(),
+        price: add_item_data.price,
+        wanted_price: add_item_data.wanted_price,
+        group_size_goal: add_item_data.group_size_goal,
+        bucketed_progress: add_item_data.bucketed_progress.unwrap_or(false),
+        deadline: add_item_data.deadline,
+        created_at: env.block.time,
+        tags: normalize_tags(add_item_data.tags)?,
+    };
+    if let Some(deadline) = static_item_data.deadline {
+        if deadline <= env.block.time {
+            return Err(StdError::generic_err("The deadline must be in the future"));
+        }
+    }
+
+    match may_load_category(&deps.storage, &static_item_data.category)? {
+        Some(category) if !category.enabled => {
+            return Err(StdError::generic_err(
+                "This category doesn't accept new items",
+            ))
+        }
+        _ => {}
+    }
+
+    let order_key = order_key(&static_item_data.category, &static_item_data.url);
+    if may_load_order(&deps.storage, &order_key)?.is_some() {
+        return Err(StdError::generic_err(
+            "An order for an item with this url already exists",
+        ));
+    }
+
     let (static_prefix, dynamic_prefix, _dynamic_prefix_users) =
-        get_category_prefixes(static_item_data.category.as_bytes())?;
+        get_category_prefixes(&deps.storage, &static_item_data.category)?;
+    if get_category_item_by_url(&deps.storage, &static_prefix, &static_item_data.url)?.is_some() {
+        return Err(StdError::generic_err(
+            "An item with this url already exists",
+        ));
+    }
 
-    save_new_item(&mut deps.storage, static_prefix, &static_item_data)?;
+    save_new_item(&mut deps.storage, &static_prefix, &static_item_data)?;
 
     let key = sha_256(base64::encode(static_item_data.url.clone()).as_bytes());
-    update_current_group_size(&mut deps.storage, &key, dynamic_prefix, 0)?;
+    update_current_group_size(&mut deps.storage, &key, &dynamic_prefix, 0)?;
+    update_stats(&mut deps.storage, &static_item_data.category, |stats| {
+        stats.active_listings += 1;
+    })?;
+    save_seller_listing(
+        &mut deps.storage,
+        &seller_canonical_address,
+        &ListingRef {
+            category: static_item_data.category,
+            url: static_item_data.url,
+        },
+    )?;
 
     Ok(HandleResponse {
         messages: vec![],
@@ -64,8 +232,40 @@ fn remove_item<S: Storage, A: Api, Q: Querier>(
     env: Env,
     remove_item_data: RemoveItemData,
 ) -> StdResult<HandleResponse> {
-    remove_item_data.authenticate_delete(deps, &env.message.sender)?;
+    remove_item_data.authenticate_delete(deps, &env.message.sender, env.block.time)?;
+
+    let (static_prefix, dynamic_prefix, _dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, &remove_item_data.category)?;
+    let item_data =
+        match get_category_item_by_url(&deps.storage, &static_prefix, &remove_item_data.url)? {
+            Some(item_data) => item_data,
+            None => return Err(StdError::generic_err("No such item")),
+        };
+    // Only the seller of the item or the admin may remove it
+    if env.message.sender.to_string() != item_data.seller_address
+        && env.message.sender != load_config(&deps.storage)?.admin
+    {
+        return Err(StdError::unauthorized());
+    }
+    // The deposits of the participants stay with the item, so they must leave first
+    let url_key = sha_256(base64::encode(&remove_item_data.url).as_bytes());
+    if get_category_item_group_size(&deps.storage, &dynamic_prefix, &url_key)?.unwrap_or(0) > 0 {
+        return Err(StdError::generic_err(
+            "Items with participants can't be removed",
+        ));
+    }
 
+    let seller_canonical_address = deps
+        .api
+        .canonical_address(&HumanAddr(item_data.seller_address))?;
+    remove_seller_listing(
+        &mut deps.storage,
+        &seller_canonical_address,
+        &ListingRef {
+            category: remove_item_data.category.clone(),
+            url: remove_item_data.url.clone(),
+        },
+    )?;
     remove_item_authenticated(&remove_item_data.category, &remove_item_data.url, deps)?;
 
     Ok(HandleResponse {
@@ -83,22 +283,41 @@ pub fn remove_item_authenticated<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
 ) -> Result<(), StdError> {
     let (static_prefix, dynamic_prefix, dynamic_prefix_users) =
-        get_category_prefixes(category.as_bytes())?;
+        get_category_prefixes(&deps.storage, category)?;
     let url_key = sha_256(base64::encode(url).as_bytes());
 
-    remove_category_item(&mut deps.storage, static_prefix, url)?;
-    remove_current_group_size(&mut deps.storage, dynamic_prefix, &url_key)?;
+    let item_data = get_category_item_by_url(&deps.storage, &static_prefix, url)?;
+    let group_size =
+        get_category_item_group_size(&deps.storage, &dynamic_prefix, &url_key)?.unwrap_or(0);
+    remove_category_item(&mut deps.storage, &static_prefix, url)?;
+    remove_current_group_size(&mut deps.storage, &dynamic_prefix, &url_key)?;
 
     let users_addresses =
-        get_all_participating_users_addresses(&deps.storage, dynamic_prefix_users, &url_key)?;
+        get_all_participating_users_addresses(&deps.storage, &dynamic_prefix_users, &url_key)?;
+
+    // Whatever the listing still holds leaves the running counters with it
+    // (the participations of items with bucketed progress were never counted)
+    if let Some(item_data) = item_data {
+        let (participants, group_size) = match item_data.bucketed_progress {
+            true => (0, 0),
+            false => (users_addresses.len() as u32, group_size),
+        };
+        let escrowed = group_size as u128 * item_data.wanted_price.u128();
+        update_stats(&mut deps.storage, category, |stats| {
+            stats.active_listings = stats.active_listings.saturating_sub(1);
+            stats.total_participants = stats.total_participants.saturating_sub(participants);
+            stats.total_units = stats.total_units.saturating_sub(group_size as u64);
+            stats.escrowed_volume = Uint128(stats.escrowed_volume.u128().saturating_sub(escrowed));
+        })?;
+    }
 
-    remove_all_category_item_users_details(&mut deps.storage, dynamic_prefix_users, &url_key)?;
+    remove_all_category_item_users_details(&mut deps.storage, &dynamic_prefix_users, &url_key)?;
 
     for user_address in users_addresses.iter() {
         // Remove the user's quantity object for this item (Find it by its URL)
         remove_user_item_quantity(
             &mut deps.storage,
-            dynamic_prefix,
+            &dynamic_prefix,
             deps.api.canonical_address(user_address)?.as_slice(),
             url,
         )?;
@@ -111,11 +330,14 @@ pub fn set_viewing_key<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     key: String,
+    expires_at: Option<u64>,
+    scopes: Option<Vec<ViewingKeyScope>>,
 ) -> StdResult<HandleResponse> {
+    check_viewing_key_expiry(&env, expires_at)?;
     let vk = ViewingKey(key);
 
     let message_sender = deps.api.canonical_address(&env.message.sender)?;
-    ViewingKey::write_viewing_key(&mut deps.storage, &message_sender, &vk);
+    ViewingKey::write_viewing_key(&mut deps.storage, &message_sender, &vk, expires_at, scopes)?;
 
     Ok(HandleResponse {
         messages: vec![],
@@ -126,18 +348,400 @@ pub fn set_viewing_key<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+pub fn create_viewing_key<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    entropy: String,
+    expires_at: Option<u64>,
+    scopes: Option<Vec<ViewingKeyScope>>,
+) -> StdResult<HandleResponse> {
+    check_viewing_key_expiry(&env, expires_at)?;
+    let config = load_config(&deps.storage)?;
+    let vk = ViewingKey::new(&env, &config.prng_seed, entropy.as_bytes());
+
+    let message_sender = deps.api.canonical_address(&env.message.sender)?;
+    ViewingKey::write_viewing_key(&mut deps.storage, &message_sender, &vk, expires_at, scopes)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::CreateViewingKey { key: vk })?),
+    })
+}
+
+fn check_viewing_key_expiry(env: &Env, expires_at: Option<u64>) -> StdResult<()> {
+    match expires_at {
+        Some(expires_at) if expires_at <= env.block.time => Err(StdError::generic_err(
+            "The viewing key would already be expired",
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn revoke_permit<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    permit_name: String,
+) -> StdResult<HandleResponse> {
+    RevokedPermits::revoke_permit(
+        &mut deps.storage,
+        PREFIX_REVOKED_PERMITS,
+        &env.message.sender,
+        &permit_name,
+    );
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RevokePermit {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn set_contact_profile<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    email: String,
+    delivery_address: String,
+) -> StdResult<HandleResponse> {
+    let user_canonical_address = deps.api.canonical_address(&env.message.sender)?;
+
+    // Participations without their own contact data refer to the profile,
+    // so they are all updated by this
+    let profile = UserContactData {
+        email,
+        delivery_address,
+    };
+    save_contact_profile(&mut deps.storage, &user_canonical_address, &profile)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::SetContactProfile {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn register_seller<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    display_name: String,
+    contact_email: String,
+    payout_address: HumanAddr,
+    public_key: Binary,
+) -> StdResult<HandleResponse> {
+    let seller_canonical_address = deps.api.canonical_address(&env.message.sender)?;
+    // Make sure the payout address is a valid one before accepting it
+    deps.api.canonical_address(&payout_address)?;
+    let bond = match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => profile.bond,
+        None => Uint128::zero(),
+    };
+
+    // Any change to the profile has to be verified again by the admin
+    let profile = SellerProfile {
+        display_name,
+        contact_email,
+        payout_address,
+        public_key,
+        verified: false,
+        bond,
+    };
+    save_seller_profile(&mut deps.storage, &seller_canonical_address, &profile)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RegisterSeller {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+/// Fails unless the sender is the admin. Returns the config
+pub fn check_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+) -> StdResult<Config> {
+    let config = load_config(&deps.storage)?;
+    if env.message.sender != config.admin {
+        return Err(StdError::unauthorized());
+    }
+    Ok(config)
+}
+
+fn set_contract_status<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    status: ContractStatus,
+) -> StdResult<HandleResponse> {
+    let mut config = check_admin(deps, &env)?;
+    config.status = status;
+    save_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::SetContractStatus {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn change_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    address: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let mut config = check_admin(deps, &env)?;
+    // Proposing again replaces the previous proposal
+    deps.api.canonical_address(&address)?;
+    config.pending_admin = Some(address);
+    save_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::ChangeAdmin {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn accept_admin<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let mut config = load_config(&deps.storage)?;
+    if config.pending_admin.as_ref() != Some(&env.message.sender) {
+        return Err(StdError::unauthorized());
+    }
+    config.admin = env.message.sender;
+    config.pending_admin = None;
+    save_config(&mut deps.storage, &config)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::AcceptAdmin {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn set_seller_verified<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    seller: HumanAddr,
+    verified: bool,
+) -> StdResult<HandleResponse> {
+    check_admin(deps, &env)?;
+
+    let seller_canonical_address = deps.api.canonical_address(&seller)?;
+    let mut profile = match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => profile,
+        None => return Err(StdError::generic_err("No such seller")),
+    };
+    profile.verified = verified;
+    save_seller_profile(&mut deps.storage, &seller_canonical_address, &profile)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::SetSellerVerified {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+const MAX_CATEGORY_KEY_LEN: usize = 32;
+const MAX_TAGS: usize = 8;
+const MAX_TAG_LEN: usize = 32;
+
+/// Trims and lowercases the tags, and drops the duplicates
+fn normalize_tags(tags: Option<Vec<String>>) -> StdResult<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.unwrap_or_default() {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN {
+            return Err(StdError::generic_err(format!(
+                "A tag must have 1 to {} characters",
+                MAX_TAG_LEN
+            )));
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    if normalized.len() > MAX_TAGS {
+        return Err(StdError::generic_err(format!(
+            "An item can't have more than {} tags",
+            MAX_TAGS
+        )));
+    }
+    Ok(normalized)
+}
+
+fn add_category<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+    name: String,
+    parent: Option<String>,
+) -> StdResult<HandleResponse> {
+    check_admin(deps, &env)?;
+
+    // The key becomes a part of storage prefixes, so it's kept short and simple
+    if key.is_empty()
+        || key.len() > MAX_CATEGORY_KEY_LEN
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(StdError::generic_err(format!(
+            "A category key must have 1 to {} lowercase letters, digits or dashes",
+            MAX_CATEGORY_KEY_LEN
+        )));
+    }
+    let mut categories = load_categories(&deps.storage)?;
+    if categories.iter().any(|category| category.key == key) {
+        return Err(StdError::generic_err(
+            "A category with this key already exists",
+        ));
+    }
+    // The parent can't change later, so the categories can't form a cycle
+    if let Some(parent) = &parent {
+        if !categories.iter().any(|category| &category.key == parent) {
+            return Err(StdError::generic_err("No such parent category"));
+        }
+    }
+    categories.push(Category {
+        key,
+        name,
+        enabled: true,
+        parent,
+    });
+    save_categories(&mut deps.storage, &categories)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::AddCategory {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn rename_category<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+    name: String,
+) -> StdResult<HandleResponse> {
+    check_admin(deps, &env)?;
+
+    let mut categories = load_categories(&deps.storage)?;
+    match categories.iter_mut().find(|category| category.key == key) {
+        Some(category) => category.name = name,
+        None => return Err(StdError::generic_err("No such category!")),
+    }
+    save_categories(&mut deps.storage, &categories)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RenameCategory {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn disable_category<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    key: String,
+) -> StdResult<HandleResponse> {
+    check_admin(deps, &env)?;
+
+    let mut categories = load_categories(&deps.storage)?;
+    match categories.iter_mut().find(|category| category.key == key) {
+        Some(category) => category.enabled = false,
+        None => return Err(StdError::generic_err("No such category!")),
+    }
+    save_categories(&mut deps.storage, &categories)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::DisableCategory {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+fn mark_order_shipped<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+) -> StdResult<HandleResponse> {
+    let order_key = order_key(&category, &url);
+    let mut order = match may_load_order(&deps.storage, &order_key)? {
+        Some(order) => order,
+        None => return Err(StdError::generic_err("No such order")),
+    };
+    if order.static_data.seller_address != env.message.sender.to_string() {
+        return Err(StdError::unauthorized());
+    }
+    if order.shipped_at.is_some() {
+        return Err(StdError::generic_err("The order was already shipped"));
+    }
+
+    order.shipped_at = Some(env.block.time);
+    save_order(&mut deps.storage, &order_key, &order)?;
+    // The participants' contact data is not needed anymore
+    remove_order_participants_contact_data(&mut deps.storage, &order_key)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::MarkOrderShipped {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use crate::contract::init;
     use crate::msg::{
-        GetItems, InitMsg, QueryMsg, UpdateItemData, UserContactData, UserItemDetails,
+        AddItemData, Catalog, CatalogFilter, CatalogSort, Categories, ContractInfo,
+        GetOrderParticipants, GetReturnRequests, InitMsg, ItemData, ListingDetail, ListingStatus,
+        MarketStats, MyParticipations, ParticipationDashboard, PriceTier, ProgressBucket, QueryMsg,
+        QueryWithPermit, ReturnStatus, SellerListings, Stats, TransactionHistory, TxAction,
+        UpdateItemData, UserContactData, UserItem, UserItemDetails, UserItemUpdate, ViewerInfo,
     };
     use crate::query::query;
-    use crate::viewing_key::ViewingKey;
+    use crate::state::{
+        get_all_category_item_users_details, get_category_item_group_size,
+        get_category_item_user_details, get_category_user_items_quantities_by_url,
+        get_ctegory_user_items_quantities, get_txs, update_category_item_user_details,
+    };
+    use crate::viewing_key::{ViewingKey, VIEWING_KEY_PREFIX};
+    use secret_toolkit::permit::{
+        pubkey_to_account, Permission, Permit, PermitParams, PermitSignature, PubKey,
+    };
 
     use super::*;
     use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage};
-    use cosmwasm_std::{coins, from_binary, HumanAddr, InitResponse, Uint128};
+    use cosmwasm_std::{
+        coins, from_binary, BankMsg, CanonicalAddr, Coin, CosmosMsg, InitResponse, ReadonlyStorage,
+    };
+    use cosmwasm_storage::{to_length_prefixed_nested, PrefixedStorage, ReadonlyPrefixedStorage};
+    use secret_toolkit::storage::{AppendStore, AppendStoreMut};
+    use std::convert::TryInto;
 
     fn init_helper() -> (
         StdResult<InitResponse>,
@@ -147,7 +751,12 @@ mod tests {
         let env = mock_env("instantiator", &coins(1000, "token"));
 
         let init_msg = InitMsg {
-            // msg: String::from("initialized"),
+            admin: None,
+            return_window: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+            fee_bps: None,
+            fee_recipient: None,
+            max_page_size: None,
         };
 
         (init(&mut deps, env, init_msg), deps)
@@ -159,6 +768,9 @@ mod tests {
         let vk: &str = "wefhjyr";
         let msg = HandleMsg::SetViewingKey {
             key: String::from(vk),
+            expires_at: None,
+            scopes: None,
+            padding: None,
         };
         let handle_result = handle(deps, mock_env("bob", &[]), msg);
         assert!(
@@ -170,10 +782,11 @@ mod tests {
         // Get the viewing key of the reply to HandleMsg::CreateViewingKey
         let answer: HandleAnswer = from_binary(&handle_result.unwrap().data.unwrap()).unwrap();
         match answer {
-            HandleAnswer::SetViewingKey { status } => match status {
-                ResponseStatus::Failure => panic!("Failed to set the viewing key"),
-                _ => {}
-            },
+            HandleAnswer::SetViewingKey { status } => {
+                if status == ResponseStatus::Failure {
+                    panic!("Failed to set the viewing key")
+                }
+            }
             _ => panic!("NOPE"),
         };
         ViewingKey(String::from(vk))
@@ -182,10 +795,11 @@ mod tests {
     fn query_history_wrong_vk(deps: Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>) {
         let wrong_vk_query_response = query(
             &deps,
-            QueryMsg::GetItems {
+            QueryMsg::MyParticipations {
                 category: String::from("laptops"),
                 address: HumanAddr("bob".to_string()),
                 key: "wrong_vk".to_string(),
+                padding: None,
             },
         );
         let error = match wrong_vk_query_response {
@@ -198,39 +812,88 @@ mod tests {
         );
     }
 
+    /// The public catalog of the laptops category, along with bob's participations in it
+    struct CategoryView {
+        items: Vec<ItemData>,
+        user_items: Vec<UserItem>,
+        contact_data: Option<UserContactData>,
+        status: ResponseStatus,
+    }
+
     fn query_category_items(
         deps: &mut Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>,
-    ) -> StdResult<GetItems> {
+    ) -> StdResult<CategoryView> {
+        let query_response = query(
+            &*deps,
+            QueryMsg::Catalog {
+                category: String::from("laptops"),
+                include_subcategories: None,
+                filter: None,
+                sort: None,
+                page: None,
+                page_size: 10,
+                padding: None,
+            },
+        )?;
+        let catalog: Catalog = from_binary(&query_response)?;
+
         let vk = invoke_set_viewing_key(deps);
         let query_response = query(
             &*deps,
-            QueryMsg::GetItems {
+            QueryMsg::MyParticipations {
                 address: HumanAddr("bob".to_string()),
                 key: vk.0,
                 category: String::from("laptops"),
+                padding: None,
             },
-        )
-        .unwrap();
-        let category_items_data: GetItems = from_binary(&query_response)?;
-        Ok(category_items_data)
+        )?;
+        let participations: MyParticipations = from_binary(&query_response)?;
+
+        assert_eq!(catalog.status, participations.status);
+        Ok(CategoryView {
+            items: catalog.items,
+            user_items: participations.user_items,
+            contact_data: participations.contact_data,
+            status: catalog.status,
+        })
     }
 
-    fn add_new_item_for_test(
+    fn register_seller_for_test(
         deps: &mut Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>,
         env: Env,
     ) {
-        let new_item_data = StaticItemData {
+        let msg = HandleMsg::RegisterSeller {
+            display_name: String::from("Cool seller"),
+            contact_email: String::from("seller@email.com"),
+            payout_address: HumanAddr(String::from("sellerPayout")),
+            public_key: Binary::from(b"seller public key"),
+            padding: None,
+        };
+        let _res = handle(deps, env, msg).unwrap();
+    }
+
+    fn create_add_msg() -> AddItemData {
+        AddItemData {
             name: String::from("Cool item"),
             category: String::from("laptops"),
             url: String::from("www.item.com"),
             img_url: String::from("www.image-item.com"),
-            seller_address: String::from("sellerAddress"),
-            seller_email: String::from("seller@email.com"),
             price: Uint128(1000),
             wanted_price: Uint128(900),
             group_size_goal: 10,
-        };
-        let msg = HandleMsg::AddItem(new_item_data);
+            bucketed_progress: None,
+            deadline: None,
+            tags: None,
+            padding: None,
+        }
+    }
+
+    fn add_new_item_for_test(
+        deps: &mut Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>,
+        env: Env,
+    ) {
+        register_seller_for_test(deps, env.clone());
+        let msg = HandleMsg::AddItem(create_add_msg());
         let _res = handle(deps, env, msg).unwrap();
     }
 
@@ -242,30 +905,38 @@ mod tests {
             category: String::from("laptops"),
             url: String::from("www.item.com"),
             verification_key: String::from("wefhjyr"),
+            padding: None,
         };
         let msg = HandleMsg::RemoveItem(remove_msg);
         let _res = handle(deps, env, msg).unwrap();
     }
 
+    /// The deposit for units of the item of add_new_item_for_test
+    fn deposit_for(units: u32) -> Vec<Coin> {
+        coins(units as u128 * 900 * 1000000, "uscrt")
+    }
+
     fn create_update_msg(quantity: u32) -> UpdateItemData {
-        let user_details = UserItemDetails {
-            account_address: HumanAddr(String::from("bob")),
-            contact_data: UserContactData {
+        let user_details = UserItemUpdate {
+            refund_address: None,
+            contact_data: Some(UserContactData {
                 delivery_address: String::from("user delivery address"),
                 email: String::from("user@email.com"),
-            },
-            quantity: quantity,
+            }),
+            sealed_contact_data: None,
+            quantity,
         };
-        let update_item_data = UpdateItemData {
+        UpdateItemData {
             category: String::from("laptops"),
             url: String::from("www.item.com"),
             user_details,
-        };
-        update_item_data
+            decoys: None,
+            padding: None,
+        }
     }
 
     fn assert_fetched_data_after_update(
-        fetched_data: GetItems,
+        fetched_data: CategoryView,
         expected_len: usize,
         expected_quantity: u32,
         expected_group_size: u32,
@@ -274,13 +945,17 @@ mod tests {
         assert_eq!(fetched_data.user_items[0].url, String::from("www.item.com"));
         assert_eq!(fetched_data.user_items[0].quantity, expected_quantity);
         assert_eq!(
-            fetched_data.contact_data.unwrap().email,
+            fetched_data.user_items[0]
+                .contact_data
+                .clone()
+                .unwrap()
+                .email,
             String::from("user@email.com")
         );
         assert_eq!(fetched_data.items[0].static_data.price, Uint128(1000));
         assert_eq!(
             fetched_data.items[0].current_group_size,
-            expected_group_size
+            Some(expected_group_size)
         );
         assert_eq!(fetched_data.status, ResponseStatus::Success);
     }
@@ -313,21 +988,115 @@ mod tests {
             .api
             .canonical_address(&HumanAddr("bob".to_string()))
             .unwrap();
-        let saved_vk = ViewingKey::read_viewing_key(&deps.storage, &bob_canonical).unwrap();
+        let saved_vk = ViewingKey::read_viewing_key(&deps.storage, &bob_canonical)
+            .unwrap()
+            .unwrap();
 
         // Verify that the key in the storage is the same as the key from HandleAnswer::CreateViewingKey
-        assert!(key.check_viewing_key(saved_vk.as_slice()));
+        assert!(key.check_viewing_key(&saved_vk.hashed_key));
     }
 
     #[test]
-    fn test_add_new_item() -> StdResult<()> {
-        // Initialize the contract
-        let (init_result, mut deps) = init_helper();
-        assert!(
-            init_result.is_ok(),
-            "Init failed: {}",
-            init_result.err().unwrap()
-        );
+    fn test_viewing_key_expiry_and_scopes() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+
+        let mut env = mock_env("bob", &[]);
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("scoped_key"),
+            expires_at: Some(env.block.time),
+            scopes: Some(vec![ViewingKeyScope::Participations]),
+            padding: None,
+        };
+        assert!(handle(&mut deps, env.clone(), msg).is_err());
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("scoped_key"),
+            expires_at: Some(env.block.time + 100),
+            scopes: Some(vec![ViewingKeyScope::Participations]),
+            padding: None,
+        };
+        let _res = handle(&mut deps, env.clone(), msg)?;
+
+        // The key reads the participations, but not the contact data
+        let participations_query = QueryMsg::MyParticipations {
+            category: String::from("laptops"),
+            address: HumanAddr::from("bob"),
+            key: String::from("scoped_key"),
+            padding: None,
+        };
+        let participations: MyParticipations =
+            from_binary(&query(&deps, participations_query.clone())?)?;
+        assert_eq!(participations.user_items[0].quantity, 3);
+        assert_eq!(participations.user_items[0].contact_data, None);
+
+        // Nor the orders of bob as a seller
+        let msg = QueryMsg::GetOrderParticipants {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            address: HumanAddr::from("bob"),
+            key: String::from("scoped_key"),
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_err());
+
+        // Any transaction past the expiry makes the key invalid
+        env.block.time += 100;
+        let msg = HandleMsg::SetContactProfile {
+            email: String::from("user@email.com"),
+            delivery_address: String::from("user delivery address"),
+            padding: None,
+        };
+        let _res = handle(&mut deps, env, msg)?;
+        assert!(query(&deps, participations_query).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_viewing_key() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+
+        let msg = HandleMsg::CreateViewingKey {
+            entropy: String::from("some entropy"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        let key = match from_binary(&handle_result.data.unwrap())? {
+            HandleAnswer::CreateViewingKey { key } => key,
+            _ => panic!("Unexpected answer to CreateViewingKey"),
+        };
+        assert!(key.0.starts_with(VIEWING_KEY_PREFIX));
+
+        let bob_canonical = deps.api.canonical_address(&HumanAddr::from("bob"))?;
+        let saved_vk = ViewingKey::read_viewing_key(&deps.storage, &bob_canonical)
+            .unwrap()
+            .unwrap();
+        assert!(key.check_viewing_key(&saved_vk.hashed_key));
+
+        let msg = QueryMsg::MyParticipations {
+            category: String::from("laptops"),
+            address: HumanAddr::from("bob"),
+            key: key.0,
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_new_item() -> StdResult<()> {
+        // Initialize the contract
+        let (init_result, mut deps) = init_helper();
+        assert!(
+            init_result.is_ok(),
+            "Init failed: {}",
+            init_result.err().unwrap()
+        );
 
         let env = mock_env("bob", &coins(2, "token"));
         add_new_item_for_test(&mut deps, env);
@@ -335,13 +1104,86 @@ mod tests {
         // Query the user's transactions history using their viewing key
         let fetched_data = query_category_items(&mut deps)?;
         assert_eq!(fetched_data.items[0].static_data.price, Uint128(1000));
-        assert_eq!(fetched_data.items[0].current_group_size, 0);
+        assert_eq!(fetched_data.items[0].current_group_size, Some(0));
 
         // // Now try to hack into bob's account using the wrong key - and fail
         query_history_wrong_vk(deps);
         Ok(())
     }
 
+    #[test]
+    fn test_add_new_item_unregistered_seller() {
+        let (_init_result, mut deps) = init_helper();
+
+        let handle_result = handle(
+            &mut deps,
+            mock_env("bob", &[]),
+            HandleMsg::AddItem(create_add_msg()),
+        );
+        assert!(handle_result.is_err());
+    }
+
+    #[test]
+    fn test_add_item_with_live_url() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(5));
+        handle(&mut deps, mock_env("bob", &deposit_for(5)), msg)?;
+
+        // Another seller can't take over the url of a live item
+        register_seller_for_test(&mut deps, mock_env("seller2", &[]));
+        let msg = HandleMsg::AddItem(create_add_msg());
+        assert!(handle(&mut deps, mock_env("seller2", &[]), msg).is_err());
+
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_fetched_data_after_update(fetched_data, 1, 5, 5);
+        let msg = HandleMsg::UpdateItem(create_update_msg(0));
+        let res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("bob"),
+                amount: coins(5 * 900 * 1000000, "uscrt"),
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_seller_verified() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+
+        add_new_item_for_test(&mut deps, mock_env("bob", &[]));
+        let fetched_data = query_category_items(&mut deps)?;
+        let seller = fetched_data.items[0].seller.clone().unwrap();
+        assert_eq!(
+            fetched_data.items[0].static_data.seller_address,
+            String::from("bob")
+        );
+        assert_eq!(seller.payout_address, HumanAddr::from("sellerPayout"));
+        assert!(!seller.verified);
+
+        // Only the admin may verify sellers
+        let msg = HandleMsg::SetSellerVerified {
+            seller: HumanAddr::from("bob"),
+            verified: true,
+            padding: None,
+        };
+        let handle_result = handle(&mut deps, mock_env("bob", &[]), msg.clone());
+        assert!(handle_result.is_err());
+
+        let _res = handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert!(fetched_data.items[0].seller.clone().unwrap().verified);
+
+        // Updating the profile revokes the verification
+        register_seller_for_test(&mut deps, mock_env("bob", &[]));
+        let fetched_data = query_category_items(&mut deps)?;
+        assert!(!fetched_data.items[0].seller.clone().unwrap().verified);
+        Ok(())
+    }
+
     #[test]
     fn test_update_new_user_item_goal_not_reached() -> StdResult<()> {
         // Initialize the contract
@@ -353,12 +1195,11 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(1);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(1)), msg).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_fetched_data_after_update(fetched_data, 1, 1, 1);
@@ -379,12 +1220,11 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(10);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_eq!(fetched_data.user_items.len(), 0);
@@ -409,17 +1249,15 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
-        let env_clone2 = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(1);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(1)), msg).unwrap();
 
         let update_item_data2 = create_update_msg(5);
         let msg2 = HandleMsg::UpdateItem(update_item_data2);
-        let _res = handle(&mut deps, env_clone2, msg2).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(4)), msg2).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_fetched_data_after_update(fetched_data, 1, 5, 5);
@@ -440,17 +1278,15 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
-        let env_clone2 = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(1);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(1)), msg).unwrap();
 
         let update_item_data2 = create_update_msg(10);
         let msg2 = HandleMsg::UpdateItem(update_item_data2);
-        let _res = handle(&mut deps, env_clone2, msg2).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(9)), msg2).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_eq!(fetched_data.user_items.len(), 0);
@@ -475,17 +1311,15 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
-        let env_clone2 = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(5);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(5)), msg).unwrap();
 
         let update_item_data2 = create_update_msg(2);
         let msg2 = HandleMsg::UpdateItem(update_item_data2);
-        let _res = handle(&mut deps, env_clone2, msg2).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg2).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_fetched_data_after_update(fetched_data, 1, 2, 2);
@@ -495,6 +1329,35 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_answers_are_padded() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(5));
+        let join_answer = handle(&mut deps, mock_env("bob", &deposit_for(5)), msg)?
+            .data
+            .unwrap();
+        assert_eq!(join_answer.len() % BLOCK_SIZE, 0);
+
+        // A refund answers exactly like a join
+        let msg = HandleMsg::UpdateItem(create_update_msg(2));
+        let refund_response = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        assert_eq!(refund_response.messages.len(), 1);
+        assert_eq!(refund_response.data.unwrap(), join_answer);
+
+        let query_answer = query_category_items(&mut deps)?;
+        assert_eq!(query_answer.items.len(), 1);
+        let msg = QueryMsg::MyParticipations {
+            category: String::from("laptops"),
+            address: HumanAddr::from("bob"),
+            key: String::from("wefhjyr"),
+            padding: None,
+        };
+        assert_eq!(query(&deps, msg)?.len() % BLOCK_SIZE, 0);
+        Ok(())
+    }
+
     #[test]
     fn test_update_existing_item_reduce_quantity_completely() -> StdResult<()> {
         // Initialize the contract
@@ -506,17 +1369,15 @@ mod tests {
         );
 
         let env = mock_env("bob", &coins(2, "token"));
-        let env_clone = env.clone();
-        let env_clone2 = env.clone();
         add_new_item_for_test(&mut deps, env);
 
         let update_item_data = create_update_msg(5);
         let msg = HandleMsg::UpdateItem(update_item_data);
-        let _res = handle(&mut deps, env_clone, msg).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(5)), msg).unwrap();
 
         let update_item_data2 = create_update_msg(0);
         let msg2 = HandleMsg::UpdateItem(update_item_data2);
-        let _res = handle(&mut deps, env_clone2, msg2).unwrap();
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg2).unwrap();
 
         let fetched_data = query_category_items(&mut deps)?;
         assert_eq!(fetched_data.user_items.len(), 0);
@@ -530,6 +1391,1856 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_update_item_deposit() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        // Joining takes exactly the price of the units, in uscrt
+        let join = || HandleMsg::UpdateItem(create_update_msg(3));
+        let underpaid = coins(3 * 900 * 1000000 - 1, "uscrt");
+        assert!(handle(&mut deps, mock_env("bob", &underpaid), join()).is_err());
+        let overpaid = coins(3 * 900 * 1000000 + 1, "uscrt");
+        assert!(handle(&mut deps, mock_env("bob", &overpaid), join()).is_err());
+        let wrong_denom = coins(3 * 900 * 1000000, "token");
+        assert!(handle(&mut deps, mock_env("bob", &wrong_denom), join()).is_err());
+        assert!(handle(&mut deps, mock_env("bob", &[]), join()).is_err());
+        handle(&mut deps, mock_env("bob", &deposit_for(3)), join())?;
+
+        // Adding units takes the price of the added units only
+        let increase = || HandleMsg::UpdateItem(create_update_msg(5));
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(5)), increase()).is_err());
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(1)), increase()).is_err());
+        handle(&mut deps, mock_env("bob", &deposit_for(2)), increase())?;
+
+        // Reducing the quantity or leaving takes no funds
+        let decrease = || HandleMsg::UpdateItem(create_update_msg(4));
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(1)), decrease()).is_err());
+        handle(&mut deps, mock_env("bob", &[]), decrease())?;
+        let leave = || HandleMsg::UpdateItem(create_update_msg(0));
+        assert!(handle(&mut deps, mock_env("bob", &coins(1, "uscrt")), leave()).is_err());
+        let res = handle(&mut deps, mock_env("bob", &[]), leave())?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("bob"),
+                amount: deposit_for(4),
+            })]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_decoys_leave_their_records_unchanged() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(4));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(4)), msg)?;
+
+        let mut update_item_data = create_update_msg(2);
+        update_item_data.decoys = Some(vec![HumanAddr::from("alice"), HumanAddr::from("carol")]);
+        let _res = handle(
+            &mut deps,
+            mock_env("bob", &deposit_for(2)),
+            HandleMsg::UpdateItem(update_item_data),
+        )?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_fetched_data_after_update(fetched_data, 1, 2, 6);
+
+        let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, "laptops")?;
+        let alice = HumanAddr::from("alice");
+        let alice_canonical = deps.api.canonical_address(&alice)?;
+        let alice_quantity = get_category_user_items_quantities_by_url(
+            &deps.storage,
+            &dynamic_prefix,
+            alice_canonical.as_slice(),
+            "www.item.com",
+        )?;
+        assert_eq!(alice_quantity.unwrap().quantity, 4);
+        let url_key = sha_256(base64::encode("www.item.com").as_bytes());
+        let alice_details =
+            get_category_item_user_details(&deps.storage, &dynamic_prefix_users, &url_key, &alice)?;
+        assert_eq!(alice_details.unwrap().quantity, 4);
+        Ok(())
+    }
+
+    /// Remembers the keys written to it, to check the storage access pattern of an update
+    struct RecordingStorage {
+        storage: MockStorage,
+        written: Vec<Vec<u8>>,
+    }
+
+    impl ReadonlyStorage for RecordingStorage {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.storage.get(key)
+        }
+    }
+
+    impl Storage for RecordingStorage {
+        fn set(&mut self, key: &[u8], value: &[u8]) {
+            self.written.push(key.to_vec());
+            self.storage.set(key, value)
+        }
+
+        fn remove(&mut self, key: &[u8]) {
+            self.written.push(key.to_vec());
+            self.storage.remove(key)
+        }
+    }
+
+    /// The writes of an update with decoys, to each account's quantities and to the item's users
+    fn record_update_with_decoys(
+        deps: &mut Extern<RecordingStorage, MockApi, MockQuerier>,
+        accounts: &[&str],
+        height: u64,
+        quantity: u32,
+        deposit: &[Coin],
+    ) -> StdResult<[Vec<Vec<u8>>; 2]> {
+        let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, "laptops")?;
+        let url_key = sha_256(base64::encode("www.item.com").as_bytes());
+        let mut quantities_prefixes = vec![];
+        for account in accounts {
+            let canonical = deps.api.canonical_address(&HumanAddr::from(*account))?;
+            quantities_prefixes.push(to_length_prefixed_nested(&[
+                &dynamic_prefix,
+                canonical.as_slice(),
+            ]));
+        }
+        let users_prefix = to_length_prefixed_nested(&[&dynamic_prefix_users, &url_key]);
+
+        let mut update_item_data = create_update_msg(quantity);
+        update_item_data.decoys = Some(accounts[1..].iter().map(|a| HumanAddr::from(*a)).collect());
+        let mut env = mock_env("bob", deposit);
+        env.block.height = height;
+        deps.storage.written.clear();
+        handle(deps, env, HandleMsg::UpdateItem(update_item_data))?;
+
+        let quantities_writes = deps
+            .storage
+            .written
+            .iter()
+            .filter(|key| quantities_prefixes.iter().any(|p| key.starts_with(p)))
+            .cloned()
+            .collect();
+        let users_writes = deps
+            .storage
+            .written
+            .iter()
+            .filter(|key| key.starts_with(&users_prefix))
+            .map(|key| key[users_prefix.len()..].to_vec())
+            .collect();
+        Ok([quantities_writes, users_writes])
+    }
+
+    #[test]
+    fn test_decoys_write_like_the_sender() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(4));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(4)), msg)?;
+        let mut deps = Extern {
+            storage: RecordingStorage {
+                storage: deps.storage,
+                written: vec![],
+            },
+            api: deps.api,
+            querier: deps.querier,
+        };
+
+        // bob joins, changes his quantity and leaves, with alice (a participant) and carol as decoys
+        let accounts = ["bob", "alice", "carol"];
+        let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, "laptops")?;
+        let url_key = sha_256(base64::encode("www.item.com").as_bytes());
+        let mut quantities_prefixes = vec![];
+        for account in accounts {
+            let canonical = deps.api.canonical_address(&HumanAddr::from(account))?;
+            quantities_prefixes.push((
+                canonical.clone(),
+                to_length_prefixed_nested(&[&dynamic_prefix, canonical.as_slice()]),
+            ));
+        }
+        let quantities_len = |storage: &RecordingStorage, canonical: &CanonicalAddr| {
+            get_ctegory_user_items_quantities(storage, &dynamic_prefix, canonical.as_slice())
+                .map(|records| records.len() as u32)
+        };
+        let users_len = |storage: &RecordingStorage| {
+            get_all_category_item_users_details(storage, &dynamic_prefix_users, &url_key)
+                .map(|records| records.len() as u32)
+        };
+        // Every key written is either the length of a store or one of its records
+        let owned = |key: &[u8], len: u32| {
+            key == b"len" || (key.len() == 4 && u32::from_be_bytes(key.try_into().unwrap()) < len)
+        };
+
+        let mut sender_positions = vec![];
+        for (height, quantity, deposit) in [(1, 5, 5), (2, 4, 0), (3, 3, 0), (4, 2, 0), (5, 0, 0)] {
+            let mut lens_before = vec![];
+            for (canonical, _prefix) in &quantities_prefixes {
+                lens_before.push(quantities_len(&deps.storage, canonical)?);
+            }
+            let users_len_before = users_len(&deps.storage)?;
+            let [quantities_writes, users_writes] = record_update_with_decoys(
+                &mut deps,
+                &accounts,
+                height,
+                quantity,
+                &deposit_for(deposit),
+            )?;
+
+            // Every account gets its quantities written...
+            for ((canonical, prefix), len_before) in quantities_prefixes.iter().zip(lens_before) {
+                let len = len_before.max(quantities_len(&deps.storage, canonical)?);
+                let writes: Vec<_> = quantities_writes
+                    .iter()
+                    .filter(|key| key.starts_with(prefix))
+                    .collect();
+                assert!(!writes.is_empty());
+                assert!(writes.iter().all(|key| owned(&key[prefix.len()..], len)));
+            }
+            // ... and a record and the length of the users of the item, in the same order
+            let len = users_len_before.max(users_len(&deps.storage)?);
+            assert_eq!(users_writes.len(), 2 * accounts.len());
+            for pair in users_writes.chunks(2) {
+                assert_eq!(pair[0].len(), 4);
+                assert!(owned(&pair[0], len));
+                assert_eq!(pair[1], b"len".to_vec());
+            }
+
+            sender_positions.push(
+                quantities_writes
+                    .iter()
+                    .position(|key| key.starts_with(&quantities_prefixes[0].1))
+                    .unwrap(),
+            );
+        }
+        // Only bob's records changed
+        let group_size = get_category_item_group_size(&deps.storage, &dynamic_prefix, &url_key)?;
+        assert_eq!(group_size, Some(4));
+        let bob = deps.api.canonical_address(&HumanAddr::from("bob"))?;
+        let bob_quantity = get_category_user_items_quantities_by_url(
+            &deps.storage,
+            &dynamic_prefix,
+            bob.as_slice(),
+            "www.item.com",
+        )?;
+        assert!(bob_quantity.is_none());
+        let users =
+            get_all_category_item_users_details(&deps.storage, &dynamic_prefix_users, &url_key)?;
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].account_address, HumanAddr::from("alice"));
+        assert_eq!(users[0].quantity, 4);
+
+        // The sender isn't always at the same place among the decoys
+        assert!(sender_positions.iter().any(|p| *p != sender_positions[0]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_records_layout() -> StdResult<()> {
+        let record = UserItemDetails {
+            account_address: HumanAddr::from("alice"),
+            refund_address: None,
+            contact_data: None,
+            sealed_contact_data: None,
+            quantity: 1,
+        };
+        let mut storage = RecordingStorage {
+            storage: MockStorage::new(),
+            written: vec![],
+        };
+        for _ in 0..2 {
+            storage.written.clear();
+            let mut users = PrefixedStorage::multilevel(&[b"users", b"item"], &mut storage);
+            AppendStoreMut::attach_or_create(&mut users)?.push(&record)?;
+        }
+        let push_writes = storage.written.clone();
+        let len = storage.get(&push_writes[1]);
+
+        // A record updated in place writes the same keys as a push, with an unchanged length
+        storage.written.clear();
+        let updated = UserItemDetails {
+            quantity: 2,
+            ..record.clone()
+        };
+        update_category_item_user_details(&mut storage, b"users", b"item", &updated)?;
+        let prefix = to_length_prefixed_nested(&[b"users", b"item"]);
+        let mut record_key = prefix.clone();
+        record_key.extend_from_slice(&0u32.to_be_bytes());
+        assert_eq!(storage.written, vec![record_key, push_writes[1].clone()]);
+        assert_eq!(storage.get(&push_writes[1]), len);
+
+        let users = ReadonlyPrefixedStorage::multilevel(&[b"users", b"item"], &storage);
+        let store = AppendStore::<UserItemDetails, _, _>::attach(&users).unwrap()?;
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get_at(0)?, updated);
+        assert_eq!(store.get_at(1)?, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_catalog_is_public() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+
+        // Nobody set a viewing key, yet the catalog and the group progress are readable
+        let msg = QueryMsg::Catalog {
+            category: String::from("laptops"),
+            include_subcategories: None,
+            filter: None,
+            sort: None,
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let catalog: Catalog = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(catalog.items.len(), 1);
+        assert_eq!(catalog.items[0].current_group_size, Some(3));
+
+        // While the user's own participations stay private
+        query_history_wrong_vk(deps);
+        Ok(())
+    }
+
+    #[test]
+    fn test_catalog_pagination() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut add_item_data = create_add_msg();
+        add_item_data.url = String::from("www.item2.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+
+        let catalog_page = |page: u32| QueryMsg::Catalog {
+            category: String::from("laptops"),
+            include_subcategories: None,
+            filter: None,
+            sort: None,
+            page: Some(page),
+            page_size: 1,
+            padding: None,
+        };
+        let first_page: Catalog = from_binary(&query(&deps, catalog_page(0))?)?;
+        assert_eq!(first_page.total, 2);
+        assert_eq!(first_page.items.len(), 1);
+        assert_eq!(first_page.items[0].static_data.url, "www.item.com");
+        let second_page: Catalog = from_binary(&query(&deps, catalog_page(1))?)?;
+        assert_eq!(second_page.items[0].static_data.url, "www.item2.com");
+        let third_page: Catalog = from_binary(&query(&deps, catalog_page(2))?)?;
+        assert_eq!(third_page.total, 2);
+        assert!(third_page.items.is_empty());
+
+        // Pages beyond any category fail instead of overflowing
+        let msg = QueryMsg::Catalog {
+            category: String::from("laptops"),
+            include_subcategories: None,
+            filter: None,
+            sort: None,
+            page: Some(u32::MAX),
+            page_size: 10,
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_err());
+        let msg = QueryMsg::Catalog {
+            category: String::from("laptops"),
+            include_subcategories: None,
+            filter: None,
+            sort: Some(CatalogSort::Newest),
+            page: Some(u32::MAX),
+            page_size: 10,
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_contract_info_and_fee() -> StdResult<()> {
+        let mut deps = mock_dependencies(20, &[]);
+        let init_msg = InitMsg {
+            admin: None,
+            return_window: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+            fee_bps: Some(100),
+            fee_recipient: Some(HumanAddr::from("treasury")),
+            max_page_size: Some(5),
+        };
+        init(&mut deps, mock_env("instantiator", &[]), init_msg)?;
+
+        let info: ContractInfo =
+            from_binary(&query(&deps, QueryMsg::ContractInfo { padding: None })?)?;
+        assert_eq!(info.admin, HumanAddr::from("instantiator"));
+        assert_eq!(info.denom, "uscrt");
+        assert_eq!(info.fee_bps, 100);
+        assert_eq!(info.max_page_size, 5);
+        assert_eq!(info.categories.len(), 4);
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+
+        let msg = QueryMsg::Catalog {
+            category: String::from("laptops"),
+            include_subcategories: None,
+            filter: None,
+            sort: None,
+            page: None,
+            page_size: 6,
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_err());
+
+        // The fee is taken out of the seller's payment
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(10));
+        let res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg)?;
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from("sellerPayout"),
+                    amount: coins(8910 * 1000000, "uscrt"),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from("treasury"),
+                    amount: coins(90 * 1000000, "uscrt"),
+                }),
+            ]
+        );
+
+        // Fees below a whole SCRT are still taken
+        let mut deps = mock_dependencies(20, &[]);
+        let init_msg = InitMsg {
+            admin: None,
+            return_window: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+            fee_bps: Some(1),
+            fee_recipient: Some(HumanAddr::from("treasury")),
+            max_page_size: None,
+        };
+        init(&mut deps, mock_env("instantiator", &[]), init_msg)?;
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(10));
+        let res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg)?;
+        assert_eq!(
+            res.messages,
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from("sellerPayout"),
+                    amount: coins(8_999_100_000, "uscrt"),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from("treasury"),
+                    amount: coins(900_000, "uscrt"),
+                }),
+            ]
+        );
+
+        // Fees above 100% are rejected
+        let init_msg = InitMsg {
+            admin: None,
+            return_window: None,
+            prng_seed: Binary::from("lolz fun yay".as_bytes()),
+            fee_bps: Some(10001),
+            fee_recipient: None,
+            max_page_size: None,
+        };
+        let mut deps = mock_dependencies(20, &[]);
+        assert!(init(&mut deps, mock_env("instantiator", &[]), init_msg).is_err());
+
+        // So are invalid admin and fee recipient addresses
+        for (admin, fee_recipient) in [("", "treasury"), ("instantiator", "")] {
+            let init_msg = InitMsg {
+                admin: Some(HumanAddr::from(admin)),
+                return_window: None,
+                prng_seed: Binary::from("lolz fun yay".as_bytes()),
+                fee_bps: Some(100),
+                fee_recipient: Some(HumanAddr::from(fee_recipient)),
+                max_page_size: None,
+            };
+            assert!(init(&mut deps, mock_env("instantiator", &[]), init_msg).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_two_step_admin_change() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        let change_admin = HandleMsg::ChangeAdmin {
+            address: HumanAddr::from("bob"),
+            padding: None,
+        };
+        let res = handle(&mut deps, mock_env("bob", &[]), change_admin.clone());
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        handle(&mut deps, mock_env("instantiator", &[]), change_admin)?;
+
+        // Until bob accepts, the role stays with the current admin
+        let add_category = |key: &str| HandleMsg::AddCategory {
+            key: String::from(key),
+            name: String::from(key),
+            parent: None,
+            padding: None,
+        };
+        let res = handle(&mut deps, mock_env("bob", &[]), add_category("monitors"));
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        let accept_admin = HandleMsg::AcceptAdmin { padding: None };
+        let res = handle(&mut deps, mock_env("carol", &[]), accept_admin.clone());
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        let info: ContractInfo =
+            from_binary(&query(&deps, QueryMsg::ContractInfo { padding: None })?)?;
+        assert_eq!(info.admin, HumanAddr::from("instantiator"));
+        assert_eq!(info.pending_admin, Some(HumanAddr::from("bob")));
+
+        handle(&mut deps, mock_env("bob", &[]), accept_admin.clone())?;
+        handle(&mut deps, mock_env("bob", &[]), add_category("monitors"))?;
+        let res = handle(
+            &mut deps,
+            mock_env("instantiator", &[]),
+            add_category("speakers"),
+        );
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        let info: ContractInfo =
+            from_binary(&query(&deps, QueryMsg::ContractInfo { padding: None })?)?;
+        assert_eq!(info.admin, HumanAddr::from("bob"));
+        assert_eq!(info.pending_admin, None);
+
+        // The proposal can't be accepted twice
+        let res = handle(&mut deps, mock_env("bob", &[]), accept_admin);
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        Ok(())
+    }
+
+    #[test]
+    fn test_contract_status_and_emergency_withdraw() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut update_item_data = create_update_msg(3);
+        update_item_data.user_details.refund_address = Some(HumanAddr::from("bob-wallet"));
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+
+        let set_status = |status: ContractStatus| HandleMsg::SetContractStatus {
+            status,
+            padding: None,
+        };
+        let msg = set_status(ContractStatus::StopAll);
+        let res = handle(&mut deps, mock_env("bob", &[]), msg);
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        let msg = set_status(ContractStatus::StopNewListingsAndJoins);
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+
+        // No new items or units, but participants can still reduce their quantity
+        let mut add_item_data = create_add_msg();
+        add_item_data.url = String::from("www.item2.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        assert!(handle(&mut deps, mock_env("seller", &[]), msg).is_err());
+        update_item_data.user_details.quantity = 5;
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(2)), msg).is_err());
+        update_item_data.user_details.quantity = 2;
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        handle(&mut deps, mock_env("bob", &[]), msg)?;
+
+        let emergency_withdraw = HandleMsg::EmergencyWithdraw {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            padding: None,
+        };
+        let res = handle(&mut deps, mock_env("bob", &[]), emergency_withdraw.clone());
+        assert!(res.is_err());
+
+        let msg = set_status(ContractStatus::StopAll);
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+        update_item_data.user_details.quantity = 1;
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        assert!(handle(&mut deps, mock_env("bob", &[]), msg).is_err());
+        let res = handle(
+            &mut deps,
+            mock_env("alice", &[]),
+            emergency_withdraw.clone(),
+        );
+        assert!(res.is_err());
+        let res = handle(
+            &mut deps,
+            mock_env("bob", &deposit_for(1)),
+            emergency_withdraw.clone(),
+        );
+        assert!(res.is_err());
+
+        // The whole deposit goes back to the refund address bob gave
+        let res = handle(&mut deps, mock_env("bob", &[]), emergency_withdraw.clone())?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("bob-wallet"),
+                amount: coins(2 * 900 * 1000000, "uscrt"),
+            })]
+        );
+        assert!(handle(&mut deps, mock_env("bob", &[]), emergency_withdraw).is_err());
+
+        let info: ContractInfo =
+            from_binary(&query(&deps, QueryMsg::ContractInfo { padding: None })?)?;
+        assert_eq!(info.contract_status, ContractStatus::StopAll);
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_registry() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        let add_category = HandleMsg::AddCategory {
+            key: String::from("monitors"),
+            name: String::from("Monitors"),
+            parent: None,
+            padding: None,
+        };
+        let res = handle(&mut deps, mock_env("bob", &[]), add_category.clone());
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+        handle(
+            &mut deps,
+            mock_env("instantiator", &[]),
+            add_category.clone(),
+        )?;
+        assert!(handle(&mut deps, mock_env("instantiator", &[]), add_category).is_err());
+
+        let mut add_item_data = create_add_msg();
+        add_item_data.category = String::from("monitors");
+        register_seller_for_test(&mut deps, mock_env("seller", &[]));
+        handle(
+            &mut deps,
+            mock_env("seller", &[]),
+            HandleMsg::AddItem(add_item_data.clone()),
+        )?;
+
+        let msg = HandleMsg::RenameCategory {
+            key: String::from("monitors"),
+            name: String::from("Screens"),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+        let msg = HandleMsg::DisableCategory {
+            key: String::from("monitors"),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+
+        let categories: Categories =
+            from_binary(&query(&deps, QueryMsg::Categories { padding: None })?)?;
+        assert_eq!(categories.categories.len(), 5);
+        assert_eq!(
+            categories.categories[4],
+            Category {
+                key: String::from("monitors"),
+                name: String::from("Screens"),
+                enabled: false,
+                parent: None,
+            }
+        );
+
+        // A disabled category keeps its items, but doesn't accept new ones
+        add_item_data.url = String::from("www.item2.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        assert!(handle(&mut deps, mock_env("seller", &[]), msg).is_err());
+        let msg = QueryMsg::Catalog {
+            category: String::from("monitors"),
+            include_subcategories: None,
+            filter: None,
+            sort: None,
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let catalog: Catalog = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(catalog.items.len(), 1);
+        let info: ContractInfo =
+            from_binary(&query(&deps, QueryMsg::ContractInfo { padding: None })?)?;
+        assert_eq!(info.categories.len(), 4);
+        assert!(!info.categories.contains(&String::from("monitors")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_subcategories_and_tags() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        let add_category = |key: &str, parent: &str| HandleMsg::AddCategory {
+            key: String::from(key),
+            name: String::from(key),
+            parent: Some(String::from(parent)),
+            padding: None,
+        };
+        let msg = add_category("mechanical", "no-such-category");
+        assert!(handle(&mut deps, mock_env("instantiator", &[]), msg).is_err());
+        let msg = add_category("mechanical", "keyboards");
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+        let msg = add_category("tenkeyless", "mechanical");
+        handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+
+        register_seller_for_test(&mut deps, mock_env("seller", &[]));
+        let add_item = |category: &str, url: &str, tags: &[&str]| {
+            let mut add_item_data = create_add_msg();
+            add_item_data.category = String::from(category);
+            add_item_data.url = String::from(url);
+            add_item_data.tags = Some(tags.iter().map(|tag| tag.to_string()).collect());
+            HandleMsg::AddItem(add_item_data)
+        };
+        let msg = add_item("keyboards", "www.wireless.com", &["Wireless", "wireless "]);
+        handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = add_item("tenkeyless", "www.tkl.com", &["wireless", "refurbished"]);
+        handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let too_many_tags = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+        let msg = add_item("keyboards", "www.tags.com", &too_many_tags);
+        assert!(handle(&mut deps, mock_env("seller", &[]), msg).is_err());
+
+        let catalog_query = |include_subcategories: bool, tag: Option<&str>| {
+            let msg = QueryMsg::Catalog {
+                category: String::from("keyboards"),
+                include_subcategories: Some(include_subcategories),
+                filter: tag.map(|tag| CatalogFilter {
+                    name: None,
+                    min_price: None,
+                    max_price: None,
+                    min_progress_percent: None,
+                    seller: None,
+                    ending_within: None,
+                    tag: Some(String::from(tag)),
+                }),
+                sort: None,
+                page: None,
+                page_size: 10,
+                padding: None,
+            };
+            let catalog: Catalog = from_binary(&query(&deps, msg).unwrap()).unwrap();
+            catalog
+                .items
+                .into_iter()
+                .map(|item| item.static_data.url)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(catalog_query(false, None), vec!["www.wireless.com"]);
+        assert_eq!(
+            catalog_query(true, None),
+            vec!["www.wireless.com", "www.tkl.com"]
+        );
+        assert_eq!(
+            catalog_query(true, Some("Refurbished")),
+            vec!["www.tkl.com"]
+        );
+        assert_eq!(
+            catalog_query(true, Some("wireless")),
+            vec!["www.wireless.com", "www.tkl.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut add_item_data = create_add_msg();
+        add_item_data.category = String::from("keyboards");
+        add_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(2));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(2)), msg)?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(1));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(0)), msg)?;
+        let mut update_item_data = create_update_msg(10);
+        update_item_data.category = String::from("keyboards");
+        update_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("carol", &deposit_for(10)), msg)?;
+
+        let stats_of = |category: Option<&str>| QueryMsg::Stats {
+            category: category.map(String::from),
+            padding: None,
+        };
+        let laptops: Stats = from_binary(&query(&deps, stats_of(Some("laptops")))?)?;
+        assert_eq!(
+            laptops.stats,
+            MarketStats {
+                active_listings: 1,
+                fulfilled_listings: 0,
+                total_participants: 2,
+                total_units: 4,
+                escrowed_volume: Uint128(3600),
+                paid_out_volume: Uint128(0),
+            }
+        );
+        let global: Stats = from_binary(&query(&deps, stats_of(None))?)?;
+        assert_eq!(
+            global.stats,
+            MarketStats {
+                active_listings: 1,
+                fulfilled_listings: 1,
+                total_participants: 2,
+                total_units: 4,
+                escrowed_volume: Uint128(3600),
+                paid_out_volume: Uint128(9000),
+            }
+        );
+
+        // Removing a listing takes it out of the counters, once its participants left
+        for participant in ["bob", "alice"] {
+            let msg = HandleMsg::UpdateItem(create_update_msg(0));
+            handle(&mut deps, mock_env(participant, &[]), msg)?;
+        }
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("wefhjyr"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        remove_item_for_test(&mut deps, mock_env("seller", &[]));
+        let global: Stats = from_binary(&query(&deps, stats_of(None))?)?;
+        assert_eq!(global.stats.active_listings, 0);
+        assert_eq!(global.stats.total_participants, 0);
+        assert_eq!(global.stats.escrowed_volume, Uint128(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_history() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        for (quantity, deposit) in [(3, 3), (5, 2), (2, 0)] {
+            let msg = HandleMsg::UpdateItem(create_update_msg(quantity));
+            let _res = handle(&mut deps, mock_env("bob", &deposit_for(deposit)), msg)?;
+        }
+        let msg = HandleMsg::UpdateItem(create_update_msg(8));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(8)), msg)?;
+
+        invoke_set_viewing_key(&mut deps);
+        let msg = QueryMsg::TransactionHistory {
+            address: HumanAddr::from("bob"),
+            key: String::from("wefhjyr"),
+            page: None,
+            page_size: 2,
+            padding: None,
+        };
+        let history: TransactionHistory = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(history.total, 3);
+        let actions: Vec<(u64, TxAction, u32, Uint128)> = history
+            .txs
+            .into_iter()
+            .map(|tx| (tx.id, tx.action, tx.quantity, tx.amount))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (2, TxAction::DecreaseQuantity, 3, Uint128(2700)),
+                (1, TxAction::IncreaseQuantity, 2, Uint128(1800)),
+            ]
+        );
+
+        // Reaching the goal is recorded as a payout to the seller
+        let seller = deps.api.canonical_address(&HumanAddr::from("seller"))?;
+        let (seller_txs, _total) = get_txs(&deps.storage, &seller, 0, 10)?;
+        assert_eq!(seller_txs.len(), 1);
+        assert_eq!(seller_txs[0].action, TxAction::SellerPayout);
+        assert_eq!(seller_txs[0].amount, Uint128(9000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_participation_dashboard() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut add_item_data = create_add_msg();
+        add_item_data.category = String::from("keyboards");
+        add_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+        let mut update_item_data = create_update_msg(10);
+        update_item_data.category = String::from("keyboards");
+        update_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg)?;
+
+        let vk = invoke_set_viewing_key(&mut deps);
+        let msg = QueryMsg::ParticipationDashboard {
+            address: HumanAddr::from("bob"),
+            key: vk.0,
+            padding: None,
+        };
+        let dashboard: ParticipationDashboard = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(dashboard.participations.len(), 2);
+
+        let open = &dashboard.participations[0];
+        assert_eq!(open.category, "laptops");
+        assert_eq!(open.status, ListingStatus::Open);
+        assert_eq!(open.paid_amount, Uint128(3 * 900));
+        assert_eq!(open.current_group_size, 3);
+        assert_eq!(
+            open.contact_data.as_ref().unwrap().email,
+            String::from("user@email.com")
+        );
+
+        let reached = &dashboard.participations[1];
+        assert_eq!(reached.category, "keyboards");
+        assert_eq!(reached.status, ListingStatus::GoalReached);
+        assert_eq!(reached.quantity, 10);
+        assert_eq!(reached.current_group_size, 10);
+        assert!(reached.contact_data.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_listing_detail_and_deadline() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        let mut env = mock_env("seller", &[]);
+        register_seller_for_test(&mut deps, env.clone());
+        let mut add_item_data = create_add_msg();
+        add_item_data.bucketed_progress = Some(true);
+        add_item_data.deadline = Some(env.block.time + 1000);
+        let _res = handle(&mut deps, env.clone(), HandleMsg::AddItem(add_item_data))?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+
+        let detail_query = |viewer: Option<ViewerInfo>| QueryMsg::ListingDetail {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            viewer,
+            padding: None,
+        };
+        let detail: ListingDetail = from_binary(&query(&deps, detail_query(None))?)?;
+        assert_eq!(detail.item.current_group_size, None);
+        assert_eq!(detail.time_remaining, Some(1000));
+        assert_eq!(
+            detail.price_tiers,
+            vec![
+                PriceTier {
+                    group_size: 1,
+                    price: Uint128(1000)
+                },
+                PriceTier {
+                    group_size: 10,
+                    price: Uint128(900)
+                }
+            ]
+        );
+        assert_eq!(detail.participation, None);
+
+        // A participant sees the exact group size and their participation
+        let vk = invoke_set_viewing_key(&mut deps);
+        let viewer = ViewerInfo {
+            address: HumanAddr::from("bob"),
+            key: vk.0,
+        };
+        let detail: ListingDetail = from_binary(&query(&deps, detail_query(Some(viewer)))?)?;
+        assert_eq!(detail.item.current_group_size, Some(3));
+        assert_eq!(detail.participation.unwrap().quantity, 3);
+
+        // Once the deadline passes, quantities can only be reduced
+        env.block.time += 1000;
+        env.message.sender = HumanAddr::from("bob");
+        env.message.sent_funds = deposit_for(1);
+        let msg = HandleMsg::UpdateItem(create_update_msg(4));
+        assert!(handle(&mut deps, env.clone(), msg).is_err());
+        env.message.sent_funds = vec![];
+        let msg = HandleMsg::UpdateItem(create_update_msg(1));
+        let _res = handle(&mut deps, env, msg)?;
+        let detail: ListingDetail = from_binary(&query(&deps, detail_query(None))?)?;
+        assert_eq!(detail.time_remaining, Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_catalog_filter_and_sort() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        let mut env = mock_env("seller", &[]);
+        add_new_item_for_test(&mut deps, env.clone());
+        let items = [
+            ("Gaming laptop", "www.gaming.com", 500, None),
+            ("Cool laptop", "www.cool.com", 700, Some(100)),
+        ];
+        for (name, url, wanted_price, ending_in) in items {
+            env.block.time += 10;
+            let mut add_item_data = create_add_msg();
+            add_item_data.name = String::from(name);
+            add_item_data.url = String::from(url);
+            add_item_data.wanted_price = Uint128(wanted_price);
+            add_item_data.deadline = ending_in.map(|ending_in| env.block.time + ending_in);
+            let _res = handle(&mut deps, env.clone(), HandleMsg::AddItem(add_item_data))?;
+        }
+
+        let catalog_query = |filter: Option<CatalogFilter>, sort: Option<CatalogSort>| {
+            let msg = QueryMsg::Catalog {
+                category: String::from("laptops"),
+                include_subcategories: None,
+                filter,
+                sort,
+                page: None,
+                page_size: 10,
+                padding: None,
+            };
+            let catalog: Catalog = from_binary(&query(&deps, msg).unwrap()).unwrap();
+            catalog
+                .items
+                .into_iter()
+                .map(|item| item.static_data.url)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            catalog_query(None, Some(CatalogSort::Newest)),
+            vec!["www.cool.com", "www.gaming.com", "www.item.com"]
+        );
+        let name_filter = CatalogFilter {
+            name: Some(String::from("COOL")),
+            min_price: None,
+            max_price: None,
+            min_progress_percent: None,
+            seller: None,
+            ending_within: None,
+            tag: None,
+        };
+        assert_eq!(
+            catalog_query(Some(name_filter.clone()), Some(CatalogSort::PriceAscending)),
+            vec!["www.cool.com", "www.item.com"]
+        );
+        let ending_filter = CatalogFilter {
+            name: None,
+            max_price: Some(Uint128(800)),
+            ending_within: Some(200),
+            ..name_filter
+        };
+        assert_eq!(
+            catalog_query(Some(ending_filter), None),
+            vec!["www.cool.com"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bucketed_progress() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        register_seller_for_test(&mut deps, mock_env("seller", &[]));
+        let mut add_item_data = create_add_msg();
+        add_item_data.bucketed_progress = Some(true);
+        let msg = HandleMsg::AddItem(add_item_data);
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+
+        // The public only sees that the group is 25%-50% full
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_eq!(fetched_data.items[0].current_group_size, None);
+        assert_eq!(
+            fetched_data.items[0].progress_bucket,
+            Some(ProgressBucket {
+                from_percent: 25,
+                to_percent: 50
+            })
+        );
+        // While the participants and the seller see the exact group size
+        assert_eq!(fetched_data.user_items[0].current_group_size, 3);
+
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("seller_key"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = QueryMsg::SellerListings {
+            address: HumanAddr::from("seller"),
+            key: String::from("seller_key"),
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let seller_listings: SellerListings = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(seller_listings.listings[0].current_group_size, 3);
+
+        // Nor do the public statistics give the exact group size away
+        let msg = HandleMsg::UpdateItem(create_update_msg(5));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(2)), msg)?;
+        let msg = QueryMsg::Stats {
+            category: Some(String::from("laptops")),
+            padding: None,
+        };
+        let stats: Stats = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(
+            stats.stats,
+            MarketStats {
+                active_listings: 1,
+                ..MarketStats::default()
+            }
+        );
+        let msg = HandleMsg::UpdateItem(create_update_msg(0));
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        let msg = HandleMsg::RemoveItem(RemoveItemData {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            verification_key: String::from("seller_key"),
+            padding: None,
+        });
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = QueryMsg::Stats {
+            category: None,
+            padding: None,
+        };
+        let stats: Stats = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(stats.stats, MarketStats::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seller_listings_across_categories() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut add_item_data = create_add_msg();
+        add_item_data.category = String::from("keyboards");
+        add_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::AddItem(add_item_data);
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(3)), msg)?;
+        let mut update_item_data = create_update_msg(10);
+        update_item_data.category = String::from("keyboards");
+        update_item_data.url = String::from("www.keyboard.com");
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg)?;
+
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("wefhjyr"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let listings_query = QueryMsg::SellerListings {
+            address: HumanAddr::from("seller"),
+            key: String::from("wefhjyr"),
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let seller_listings: SellerListings = from_binary(&query(&deps, listings_query.clone())?)?;
+        assert_eq!(seller_listings.total, 2);
+        let open = &seller_listings.listings[0];
+        assert_eq!(open.static_data.category, "laptops");
+        assert_eq!(open.status, ListingStatus::Open);
+        assert_eq!(open.escrowed_amount, Uint128(3 * 900));
+        let reached = &seller_listings.listings[1];
+        assert_eq!(reached.static_data.category, "keyboards");
+        assert_eq!(reached.status, ListingStatus::GoalReached);
+        assert_eq!(reached.current_group_size, 10);
+        assert_eq!(reached.escrowed_amount, Uint128(0));
+
+        // A removed item leaves the index
+        let msg = HandleMsg::UpdateItem(create_update_msg(0));
+        handle(&mut deps, mock_env("bob", &[]), msg)?;
+        remove_item_for_test(&mut deps, mock_env("seller", &[]));
+        let seller_listings: SellerListings = from_binary(&query(&deps, listings_query)?)?;
+        assert_eq!(seller_listings.total, 1);
+        assert_eq!(
+            seller_listings.listings[0].static_data.category,
+            "keyboards"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_participation_is_bound_to_sender() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(5));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(5)), msg)?;
+
+        // Alice's update creates her own participation, and can't touch bob's
+        let msg = HandleMsg::UpdateItem(create_update_msg(1));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(1)), msg)?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_fetched_data_after_update(fetched_data, 1, 5, 6);
+
+        // The refund address can't be the contract itself
+        let mut update_item_data = create_update_msg(0);
+        update_item_data.user_details.refund_address = Some(HumanAddr::from("cosmos2contract"));
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        assert!(handle(&mut deps, mock_env("bob", &[]), msg).is_err());
+
+        // Leaving the group refunds bob's share to the given refund address
+        update_item_data.user_details.refund_address = Some(HumanAddr::from("bob-wallet"));
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("bob-wallet"),
+                amount: coins(5 * 900 * 1000000, "uscrt"),
+            })]
+        );
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_eq!(fetched_data.user_items.len(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_contact_profile_propagates_to_participations() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        // Without a profile, joining requires contact data for the item
+        let mut update_item_data = create_update_msg(1);
+        update_item_data.user_details.contact_data = None;
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(1)), msg.clone()).is_err());
+
+        let set_profile_msg = |email: &str| HandleMsg::SetContactProfile {
+            email: String::from(email),
+            delivery_address: String::from("profile delivery address"),
+            padding: None,
+        };
+        let _res = handle(
+            &mut deps,
+            mock_env("bob", &[]),
+            set_profile_msg("old@email.com"),
+        )?;
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(1)), msg)?;
+
+        let _res = handle(
+            &mut deps,
+            mock_env("bob", &[]),
+            set_profile_msg("new@email.com"),
+        )?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_eq!(
+            fetched_data.contact_data.unwrap().email,
+            String::from("new@email.com")
+        );
+        assert_eq!(
+            fetched_data.user_items[0]
+                .contact_data
+                .clone()
+                .unwrap()
+                .email,
+            String::from("new@email.com")
+        );
+
+        // An override for the item takes precedence over the profile
+        let msg = HandleMsg::UpdateItem(create_update_msg(2));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(1)), msg)?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_eq!(
+            fetched_data.user_items[0]
+                .contact_data
+                .clone()
+                .unwrap()
+                .email,
+            String::from("user@email.com")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sealed_contact_data() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        let sealed_contact_data = Binary::from("encrypted to the seller's public key".as_bytes());
+        let mut update_item_data = create_update_msg(10);
+        update_item_data.user_details.sealed_contact_data = Some(sealed_contact_data.clone());
+        // Sealed contact data can't come with plaintext contact data
+        let msg = HandleMsg::UpdateItem(update_item_data.clone());
+        assert!(handle(&mut deps, mock_env("bob", &deposit_for(10)), msg).is_err());
+
+        update_item_data.user_details.contact_data = None;
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(10)), msg)?;
+
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("seller_key"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = QueryMsg::GetOrderParticipants {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            address: HumanAddr::from("seller"),
+            key: String::from("seller_key"),
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let order: GetOrderParticipants = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(order.participants[0].contact_data, None);
+        assert_eq!(
+            order.participants[0].sealed_contact_data,
+            Some(sealed_contact_data)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantity_update_keeps_contact_data() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        // bob overrides his contact data for the item and alice seals hers
+        let msg = HandleMsg::UpdateItem(create_update_msg(2));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(2)), msg)?;
+        let sealed_contact_data = Binary::from("encrypted to the seller's public key".as_bytes());
+        let mut update_item_data = create_update_msg(2);
+        update_item_data.user_details.contact_data = None;
+        update_item_data.user_details.sealed_contact_data = Some(sealed_contact_data.clone());
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(2)), msg)?;
+
+        // Both change their quantity only
+        for (account, quantity, deposit) in [("bob", 3, 1), ("alice", 1, 0)] {
+            let mut update_item_data = create_update_msg(quantity);
+            update_item_data.user_details.contact_data = None;
+            let msg = HandleMsg::UpdateItem(update_item_data);
+            let _res = handle(&mut deps, mock_env(account, &deposit_for(deposit)), msg)?;
+        }
+
+        let (_static_prefix, _dynamic_prefix, dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, "laptops")?;
+        let url_key = sha_256(base64::encode("www.item.com").as_bytes());
+        let bob_details = get_category_item_user_details(
+            &deps.storage,
+            &dynamic_prefix_users,
+            &url_key,
+            &HumanAddr::from("bob"),
+        )?
+        .unwrap();
+        assert_eq!(bob_details.quantity, 3);
+        assert_eq!(
+            bob_details.contact_data,
+            create_update_msg(2).user_details.contact_data
+        );
+        let alice_details = get_category_item_user_details(
+            &deps.storage,
+            &dynamic_prefix_users,
+            &url_key,
+            &HumanAddr::from("alice"),
+        )?
+        .unwrap();
+        assert_eq!(alice_details.quantity, 1);
+        assert_eq!(alice_details.contact_data, None);
+        assert_eq!(alice_details.sealed_contact_data, Some(sealed_contact_data));
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_participants_until_shipped() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(4));
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(4)), msg)?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(6));
+        let _res = handle(&mut deps, mock_env("bob", &deposit_for(6)), msg)?;
+
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("seller_key"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let order_query = |page: u32, address: &str, key: &str| QueryMsg::GetOrderParticipants {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            address: HumanAddr::from(address),
+            key: String::from(key),
+            page: Some(page),
+            page_size: 1,
+            padding: None,
+        };
+
+        let first_page: GetOrderParticipants =
+            from_binary(&query(&deps, order_query(0, "seller", "seller_key"))?)?;
+        assert_eq!(first_page.total, 2);
+        assert_eq!(first_page.participants.len(), 1);
+        assert_eq!(
+            first_page.participants[0].account_address,
+            HumanAddr::from("alice")
+        );
+        assert_eq!(first_page.participants[0].quantity, 4);
+        assert_eq!(first_page.shipped_at, None);
+        let second_page: GetOrderParticipants =
+            from_binary(&query(&deps, order_query(1, "seller", "seller_key"))?)?;
+        assert_eq!(
+            second_page.participants[0].account_address,
+            HumanAddr::from("bob")
+        );
+        assert_eq!(
+            second_page.participants[0]
+                .contact_data
+                .clone()
+                .unwrap()
+                .email,
+            String::from("user@email.com")
+        );
+
+        // Only the seller can see the participants of the order
+        let vk = invoke_set_viewing_key(&mut deps);
+        assert!(query(&deps, order_query(0, "bob", &vk.0)).is_err());
+
+        let msg = HandleMsg::MarkOrderShipped {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            padding: None,
+        };
+        assert!(handle(&mut deps, mock_env("bob", &[]), msg.clone()).is_err());
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+
+        let first_page: GetOrderParticipants =
+            from_binary(&query(&deps, order_query(0, "seller", "seller_key"))?)?;
+        assert_eq!(first_page.total, 2);
+        assert_eq!(first_page.participants[0].quantity, 4);
+        assert_eq!(first_page.participants[0].contact_data, None);
+        assert!(first_page.shipped_at.is_some());
+        Ok(())
+    }
+
+    fn ship_order_for_test(
+        deps: &mut Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>,
+    ) -> StdResult<()> {
+        add_new_item_for_test(deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(10));
+        let _res = handle(deps, mock_env("bob", &deposit_for(10)), msg)?;
+        let msg = HandleMsg::MarkOrderShipped {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            padding: None,
+        };
+        let _res = handle(deps, mock_env("seller", &[]), msg)?;
+        Ok(())
+    }
+
+    fn query_bob_return_requests(
+        deps: &mut Extern<cosmwasm_std::MemoryStorage, MockApi, MockQuerier>,
+    ) -> StdResult<GetReturnRequests> {
+        let vk = invoke_set_viewing_key(deps);
+        let msg = QueryMsg::GetReturnRequests {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            address: HumanAddr::from("bob"),
+            key: vk.0,
+            padding: None,
+        };
+        from_binary(&query(&*deps, msg)?)
+    }
+
+    #[test]
+    fn test_return_approved_from_bond() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        ship_order_for_test(&mut deps)?;
+
+        let request_return_msg = |quantity: u32| HandleMsg::RequestReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            quantity,
+            reason: String::from("Broken screen"),
+            padding: None,
+        };
+        // Only participants can ask for a return, and not for more than they bought
+        assert!(handle(&mut deps, mock_env("alice", &[]), request_return_msg(1)).is_err());
+        assert!(handle(&mut deps, mock_env("bob", &[]), request_return_msg(11)).is_err());
+        let _res = handle(&mut deps, mock_env("bob", &[]), request_return_msg(2))?;
+        assert!(handle(&mut deps, mock_env("bob", &[]), request_return_msg(1)).is_err());
+
+        let approve_msg = HandleMsg::ApproveReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            buyer: HumanAddr::from("bob"),
+            padding: None,
+        };
+        // The seller didn't deposit a bond yet
+        assert!(handle(&mut deps, mock_env("seller", &[]), approve_msg.clone()).is_err());
+
+        let msg = HandleMsg::DepositSellerBond { padding: None };
+        let _res = handle(
+            &mut deps,
+            mock_env("seller", &coins(1_800_000_000, "uscrt")),
+            msg,
+        )?;
+        assert!(handle(&mut deps, mock_env("bob", &[]), approve_msg.clone()).is_err());
+        let res = handle(&mut deps, mock_env("seller", &[]), approve_msg)?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("bob"),
+                amount: coins(1_800_000_000, "uscrt"),
+            })]
+        );
+
+        let return_requests = query_bob_return_requests(&mut deps)?.return_requests;
+        assert_eq!(return_requests.len(), 1);
+        assert_eq!(return_requests[0].quantity, 2);
+        assert_eq!(return_requests[0].status, ReturnStatus::Approved);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantity_update_keeps_refund_address() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let mut update_item_data = create_update_msg(3);
+        update_item_data.user_details.refund_address = Some(HumanAddr::from("alice-wallet"));
+        let msg = HandleMsg::UpdateItem(update_item_data);
+        let _res = handle(&mut deps, mock_env("alice", &deposit_for(3)), msg)?;
+
+        // Neither the decrease nor the leave send the refund address again
+        for quantity in [2, 0] {
+            let msg = HandleMsg::UpdateItem(create_update_msg(quantity));
+            let res = handle(&mut deps, mock_env("alice", &[]), msg)?;
+            assert_eq!(
+                res.messages,
+                vec![CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from("alice-wallet"),
+                    amount: deposit_for(if quantity == 2 { 1 } else { 2 }),
+                })]
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_withdraw_seller_bond() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        ship_order_for_test(&mut deps)?;
+        let msg = HandleMsg::DepositSellerBond { padding: None };
+        let _res = handle(&mut deps, mock_env("seller", &deposit_for(12)), msg)?;
+
+        let withdraw_msg = |amount: u32| HandleMsg::WithdrawSellerBond {
+            amount: Uint128(amount as u128 * 900 * 1000000),
+            padding: None,
+        };
+        // The 10 units bob bought may still be returned
+        assert!(handle(&mut deps, mock_env("bob", &[]), withdraw_msg(1)).is_err());
+        assert!(handle(&mut deps, mock_env("seller", &[]), withdraw_msg(3)).is_err());
+        let res = handle(&mut deps, mock_env("seller", &[]), withdraw_msg(2))?;
+        assert_eq!(
+            res.messages,
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                from_address: HumanAddr::from("cosmos2contract"),
+                to_address: HumanAddr::from("seller"),
+                amount: deposit_for(2),
+            })]
+        );
+
+        // Once the return window is over, only the pending return keeps its units locked
+        let msg = HandleMsg::RequestReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            quantity: 3,
+            reason: String::from("Broken screen"),
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        let mut env = mock_env("seller", &[]);
+        env.block.time += 14 * 24 * 60 * 60 + 1;
+        assert!(handle(&mut deps, env.clone(), withdraw_msg(8)).is_err());
+        let _res = handle(&mut deps, env.clone(), withdraw_msg(7))?;
+        let msg = HandleMsg::ApproveReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            buyer: HumanAddr::from("bob"),
+            padding: None,
+        };
+        let _res = handle(&mut deps, env.clone(), msg)?;
+        assert!(handle(&mut deps, env, withdraw_msg(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_return_refund_goes_to_refund_address() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        // alice joins before the goal is reached and bob completes the group
+        for (account, quantity) in [("alice", 4), ("bob", 6)] {
+            let mut update_item_data = create_update_msg(quantity);
+            update_item_data.user_details.refund_address =
+                Some(HumanAddr::from(format!("{}-wallet", account)));
+            let msg = HandleMsg::UpdateItem(update_item_data);
+            handle(&mut deps, mock_env(account, &deposit_for(quantity)), msg)?;
+        }
+        let msg = HandleMsg::MarkOrderShipped {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            padding: None,
+        };
+        handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = HandleMsg::DepositSellerBond { padding: None };
+        handle(&mut deps, mock_env("seller", &deposit_for(2)), msg)?;
+
+        for account in ["alice", "bob"] {
+            let msg = HandleMsg::RequestReturn {
+                category: String::from("laptops"),
+                url: String::from("www.item.com"),
+                quantity: 1,
+                reason: String::from("Broken screen"),
+                padding: None,
+            };
+            handle(&mut deps, mock_env(account, &[]), msg)?;
+            let msg = HandleMsg::ApproveReturn {
+                category: String::from("laptops"),
+                url: String::from("www.item.com"),
+                buyer: HumanAddr::from(account),
+                padding: None,
+            };
+            let res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+            assert_eq!(
+                res.messages,
+                vec![CosmosMsg::Bank(BankMsg::Send {
+                    from_address: HumanAddr::from("cosmos2contract"),
+                    to_address: HumanAddr::from(format!("{}-wallet", account)),
+                    amount: deposit_for(1),
+                })]
+            );
+        }
+
+        // The seller doesn't learn where the refunds go
+        let msg = HandleMsg::SetViewingKey {
+            key: String::from("seller_key"),
+            expires_at: None,
+            scopes: None,
+            padding: None,
+        };
+        handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let msg = QueryMsg::GetOrderParticipants {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            address: HumanAddr::from("seller"),
+            key: String::from("seller_key"),
+            page: None,
+            page_size: 10,
+            padding: None,
+        };
+        let order_participants: GetOrderParticipants = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(order_participants.participants.len(), 2);
+        for participant in order_participants.participants {
+            assert_eq!(participant.refund_address, None);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_return_rejected_and_disputed() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        ship_order_for_test(&mut deps)?;
+
+        let msg = HandleMsg::RequestReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            quantity: 1,
+            reason: String::from("Broken screen"),
+            padding: None,
+        };
+        let mut late_env = mock_env("bob", &[]);
+        late_env.block.time += 15 * 24 * 60 * 60;
+        assert!(handle(&mut deps, late_env, msg.clone()).is_err());
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+
+        let msg = HandleMsg::RejectReturn {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            buyer: HumanAddr::from("bob"),
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("seller", &[]), msg)?;
+        let return_requests = query_bob_return_requests(&mut deps)?.return_requests;
+        assert_eq!(return_requests[0].status, ReturnStatus::Disputed);
+
+        let msg = HandleMsg::ResolveReturnDispute {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            buyer: HumanAddr::from("bob"),
+            refund: false,
+            padding: None,
+        };
+        assert!(handle(&mut deps, mock_env("seller", &[]), msg.clone()).is_err());
+        let _res = handle(&mut deps, mock_env("instantiator", &[]), msg)?;
+        let return_requests = query_bob_return_requests(&mut deps)?.return_requests;
+        assert_eq!(return_requests[0].status, ReturnStatus::Rejected);
+        Ok(())
+    }
+
+    /// A permit for this contract, named "g-buy", with the `balance` permission only, signed for
+    /// real by a key whose address MockApi can represent
+    fn balance_permit_for_test() -> Permit {
+        Permit {
+            params: PermitParams {
+                allowed_tokens: vec![HumanAddr::from("cosmos2contract")],
+                permit_name: String::from("g-buy"),
+                chain_id: String::from("secret-4"),
+                permissions: vec![Permission::Balance],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: String::from("tendermint/PubKeySecp256k1"),
+                    value: Binary::from_base64("Al/lCWDt+TEP+9MRQwXsOWCfPKMNwRFW6SJCScPap1Ba")
+                        .unwrap(),
+                },
+                signature: Binary::from_base64(
+                    "lPwvIRN1VNs5OyYC4iKX1rXxtzVMhCag40iVLQXl6DlRdRylNS1kbMV1Ecrz5OE9cCxoZr9tGTPQQux5rU8fMQ==",
+                )
+                .unwrap(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_permit_permissions() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let permit = balance_permit_for_test();
+        let signer = deps
+            .api
+            .human_address(&pubkey_to_account(&permit.signature.pub_key.value))?;
+        let msg = HandleMsg::UpdateItem(create_update_msg(3));
+        handle(&mut deps, mock_env(signer.clone(), &deposit_for(3)), msg)?;
+
+        // The balance permission covers the participant's queries
+        let msg = QueryMsg::WithPermit {
+            permit: permit.clone(),
+            query: QueryWithPermit::MyParticipations {
+                category: String::from("laptops"),
+            },
+            padding: None,
+        };
+        let participations: MyParticipations = from_binary(&query(&deps, msg)?)?;
+        assert_eq!(participations.user_items.len(), 1);
+        assert_eq!(participations.user_items[0].quantity, 3);
+
+        // But not the histories, which need the history permission
+        let msg = QueryMsg::WithPermit {
+            permit: permit.clone(),
+            query: QueryWithPermit::TransactionHistory {
+                page: None,
+                page_size: 10,
+            },
+            padding: None,
+        };
+        let query_result = query(&deps, msg);
+        assert!(query_result.unwrap_err().to_string().contains("History"));
+
+        // Nor does it work once revoked
+        let msg = HandleMsg::RevokePermit {
+            permit_name: String::from("g-buy"),
+            padding: None,
+        };
+        handle(&mut deps, mock_env(signer, &[]), msg)?;
+        let msg = QueryMsg::WithPermit {
+            permit,
+            query: QueryWithPermit::MyParticipations {
+                category: String::from("laptops"),
+            },
+            padding: None,
+        };
+        assert!(query(&deps, msg).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_permit_for_another_contract() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+
+        let permit = Permit {
+            params: PermitParams {
+                allowed_tokens: vec![HumanAddr::from("another-contract")],
+                permit_name: String::from("g-buy"),
+                chain_id: String::from("secret-4"),
+                permissions: vec![Permission::Owner],
+            },
+            signature: PermitSignature {
+                pub_key: PubKey {
+                    r#type: String::from("tendermint/PubKeySecp256k1"),
+                    value: Binary::from(vec![2u8; 33]),
+                },
+                signature: Binary::from(vec![1u8; 64]),
+            },
+        };
+        let msg = QueryMsg::WithPermit {
+            permit,
+            query: QueryWithPermit::MyParticipations {
+                category: String::from("laptops"),
+            },
+            padding: None,
+        };
+        let query_result = query(&deps, msg);
+        assert!(query_result.is_err());
+
+        let msg = HandleMsg::RevokePermit {
+            permit_name: String::from("g-buy"),
+            padding: None,
+        };
+        let _res = handle(&mut deps, mock_env("bob", &[]), msg)?;
+        assert!(RevokedPermits::is_permit_revoked(
+            &deps.storage,
+            PREFIX_REVOKED_PERMITS,
+            &HumanAddr::from("bob"),
+            "g-buy"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_item_restrictions() -> StdResult<()> {
+        let (_init_result, mut deps) = init_helper();
+        add_new_item_for_test(&mut deps, mock_env("seller", &[]));
+        let msg = HandleMsg::UpdateItem(create_update_msg(2));
+        handle(&mut deps, mock_env("bob", &deposit_for(2)), msg)?;
+        for account in ["bob", "seller", "instantiator"] {
+            let msg = HandleMsg::SetViewingKey {
+                key: String::from("wefhjyr"),
+                expires_at: None,
+                scopes: None,
+                padding: None,
+            };
+            handle(&mut deps, mock_env(account, &[]), msg)?;
+        }
+        let remove_msg = HandleMsg::RemoveItem(RemoveItemData {
+            category: String::from("laptops"),
+            url: String::from("www.item.com"),
+            verification_key: String::from("wefhjyr"),
+            padding: None,
+        });
+
+        // A valid viewing key isn't enough to remove someone else's item
+        let res = handle(&mut deps, mock_env("bob", &[]), remove_msg.clone());
+        assert_eq!(res.unwrap_err(), StdError::unauthorized());
+
+        // Not even the seller can remove an item that holds deposits
+        let res = handle(&mut deps, mock_env("seller", &[]), remove_msg.clone());
+        assert!(res.is_err());
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_fetched_data_after_update(fetched_data, 1, 2, 2);
+
+        let msg = HandleMsg::UpdateItem(create_update_msg(0));
+        handle(&mut deps, mock_env("bob", &[]), msg)?;
+        handle(&mut deps, mock_env("instantiator", &[]), remove_msg)?;
+        let fetched_data = query_category_items(&mut deps)?;
+        assert_eq!(fetched_data.items.len(), 0);
+        Ok(())
+    }
+
     #[test]
     fn test_remove_item() -> StdResult<()> {
         // Initialize the contract
@@ -547,7 +3258,7 @@ mod tests {
         // Query the user's transactions history using their viewing key
         let fetched_data = query_category_items(&mut deps)?;
         assert_eq!(fetched_data.items[0].static_data.price, Uint128(1000));
-        assert_eq!(fetched_data.items[0].current_group_size, 0);
+        assert_eq!(fetched_data.items[0].current_group_size, Some(0));
 
         remove_item_for_test(&mut deps, env2);
         let fetched_data2 = query_category_items(&mut deps)?;
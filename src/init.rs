@@ -1,11 +1,67 @@
-use cosmwasm_std::{Api, Env, Extern, InitResponse, Querier, StdResult, Storage};
+use cosmwasm_std::{Api, Env, Extern, InitResponse, Querier, StdError, StdResult, Storage};
+use secret_toolkit::crypto::sha_256;
 
-use crate::msg::InitMsg;
+use crate::{
+    msg::{Category, ContractStatus, InitMsg},
+    state::{save_block_time, save_categories, save_config, Config, MAX_FEE_BPS},
+};
+
+/// 14 days
+const DEFAULT_RETURN_WINDOW: u64 = 14 * 24 * 60 * 60;
+const DEFAULT_MAX_PAGE_SIZE: u32 = 100;
+/// The categories the contract starts with, as (key, name)
+const DEFAULT_CATEGORIES: &[(&str, &str)] = &[
+    ("laptops", "Laptops"),
+    ("keyboards", "Keyboards"),
+    ("mouses", "Mouses"),
+    ("mouse-pads", "Mouse pads"),
+];
 
 pub fn init<S: Storage, A: Api, Q: Querier>(
-    _deps: &mut Extern<S, A, Q>,
-    _env: Env,
-    _msg: InitMsg,
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    msg: InitMsg,
 ) -> StdResult<InitResponse> {
+    save_block_time(&mut deps.storage, env.block.time)?;
+    let fee_bps = msg.fee_bps.unwrap_or(0);
+    if fee_bps > MAX_FEE_BPS {
+        return Err(StdError::generic_err(format!(
+            "The fee can't exceed {} basis points",
+            MAX_FEE_BPS
+        )));
+    }
+    let max_page_size = msg.max_page_size.unwrap_or(DEFAULT_MAX_PAGE_SIZE);
+    if max_page_size == 0 {
+        return Err(StdError::generic_err("The max page size must be positive"));
+    }
+    let admin = msg.admin.unwrap_or(env.message.sender);
+    let fee_recipient = msg.fee_recipient.unwrap_or_else(|| admin.clone());
+    // Funds and the admin role can't be handed to an address that doesn't exist
+    deps.api.canonical_address(&admin)?;
+    deps.api.canonical_address(&fee_recipient)?;
+    let config = Config {
+        fee_recipient,
+        admin,
+        pending_admin: None,
+        status: ContractStatus::Normal,
+        return_window: msg.return_window.unwrap_or(DEFAULT_RETURN_WINDOW),
+        fee_bps,
+        max_page_size,
+        contract_address: env.contract.address,
+        prng_seed: sha_256(msg.prng_seed.as_slice()).to_vec(),
+    };
+    save_config(&mut deps.storage, &config)?;
+
+    let categories: Vec<Category> = DEFAULT_CATEGORIES
+        .iter()
+        .map(|(key, name)| Category {
+            key: key.to_string(),
+            name: name.to_string(),
+            enabled: true,
+            parent: None,
+        })
+        .collect();
+    save_categories(&mut deps.storage, &categories)?;
+
     Ok(InitResponse::default())
 }
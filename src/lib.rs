@@ -1,5 +1,6 @@
 pub mod contract;
 pub mod msg;
+mod return_logic;
 pub mod state;
 mod update_logic;
 mod utils;
@@ -1,11 +1,31 @@
-use cosmwasm_std::{Api, Extern, HumanAddr, Querier, StdError, StdResult, Storage, Uint128};
+use cosmwasm_std::{
+    Api, Binary, Extern, HumanAddr, Querier, StdError, StdResult, Storage, Uint128,
+};
 use schemars::JsonSchema;
+use secret_toolkit::permit::Permit;
 use serde::{Deserialize, Serialize};
 
-use crate::viewing_key::{ViewingKey, VIEWING_KEY_SIZE};
+use crate::{
+    state::load_block_time,
+    viewing_key::{StoredViewingKey, ViewingKey, ViewingKeyScope, VIEWING_KEY_SIZE},
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct InitMsg {}
+pub struct InitMsg {
+    /// The address allowed to perform administrative actions. Defaults to the instantiator
+    pub admin: Option<HumanAddr>,
+    /// For how long (in seconds) after an order is shipped its participants may ask for a
+    /// return. Defaults to 14 days
+    pub return_window: Option<u64>,
+    /// Seeds the generation of viewing keys in CreateViewingKey
+    pub prng_seed: Binary,
+    /// The part of each group payment kept as a fee, in basis points. Defaults to 0
+    pub fee_bps: Option<u16>,
+    /// Where the fees are sent. Defaults to the admin
+    pub fee_recipient: Option<HumanAddr>,
+    /// The largest page the paginated queries return. Defaults to 100
+    pub max_page_size: Option<u32>,
+}
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -22,22 +42,80 @@ pub struct UserContactData {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct UserItemDetails {
+    /// Always the address that joined the item
     pub account_address: HumanAddr,
-    pub contact_data: UserContactData,
+    /// Where refunds for this item are sent, instead of the account address
+    pub refund_address: Option<HumanAddr>,
+    /// Overrides the user's contact profile for this item only
+    pub contact_data: Option<UserContactData>,
+    /// The contact data encrypted to the seller's public key, so only the seller can read it.
+    /// Replaces both the override and the profile
+    pub sealed_contact_data: Option<Binary>,
+    pub quantity: u32,
+}
+
+/// The participation details a user sends. The participant is always the sender
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserItemUpdate {
+    /// Defaults to the sender. Kept from the current participation when not sent
+    pub refund_address: Option<HumanAddr>,
+    /// Kept from the current participation when neither kind of contact data is sent
+    pub contact_data: Option<UserContactData>,
+    pub sealed_contact_data: Option<Binary>,
     pub quantity: u32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SellerProfile {
+    pub display_name: String,
+    pub contact_email: String,
+    /// The address the seller gets paid to once a group reaches its goal
+    pub payout_address: HumanAddr,
+    /// Participants may encrypt their contact data to this key, so only the seller can read it
+    pub public_key: Binary,
+    /// Set by the admin only, reset whenever the seller updates the profile
+    pub verified: bool,
+    /// The amount (in uscrt) deposited by the seller to cover approved returns
+    pub bond: Uint128,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct StaticItemData {
     pub name: String,
     pub category: String,
     pub url: String,
     pub img_url: String,
+    /// References the seller profile. Always set to the address that added the item
     pub seller_address: String,
-    pub seller_email: String,
     pub price: Uint128,
     pub wanted_price: Uint128,
     pub group_size_goal: u32,
+    /// The public catalog only shows the progress of the group in buckets of 25%
+    pub bucketed_progress: bool,
+    /// The block time (in seconds) from which users can't join the group or increase their quantity
+    pub deadline: Option<u64>,
+    /// The block time (in seconds) in which the item was added
+    pub created_at: u64,
+    /// Lowercase labels that cut across categories, like "wireless"
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AddItemData {
+    pub name: String,
+    pub category: String,
+    pub url: String,
+    pub img_url: String,
+    pub price: Uint128,
+    pub wanted_price: Uint128,
+    pub group_size_goal: u32,
+    /// Hides the exact group size from the public catalog. Defaults to false
+    pub bucketed_progress: Option<bool>,
+    /// The block time (in seconds) at which the group stops accepting new units. None for no deadline
+    pub deadline: Option<u64>,
+    /// Up to 8 tags of up to 32 characters each. Stored in lowercase
+    pub tags: Option<Vec<String>>,
+    pub padding: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -45,7 +123,11 @@ pub struct UpdateItemData {
     /// url is the unique identifier of the product (could be also the creator address)
     pub category: String,
     pub url: String,
-    pub user_details: UserItemDetails,
+    pub user_details: UserItemUpdate,
+    /// Addresses whose records of the item are read and rewritten unchanged, so that the real
+    /// participant can't be singled out by the storage access
+    pub decoys: Option<Vec<HumanAddr>>,
+    pub padding: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -53,37 +135,75 @@ pub struct RemoveItemData {
     pub category: String,
     pub url: String,
     pub verification_key: String,
+    pub padding: Option<String>,
 }
 impl RemoveItemData {
     pub fn authenticate_delete<S: Storage, A: Api, Q: Querier>(
         &self,
         deps: &Extern<S, A, Q>,
         address: &HumanAddr,
+        block_time: u64,
     ) -> StdResult<()> {
-        // Todo: Authenticate against the address of the seller! not with the user who tries to delete
         let vk = ViewingKey(self.verification_key.clone());
 
         let canonical_addr = deps.api.canonical_address(address)?;
 
-        let expected_key = ViewingKey::read_viewing_key(&deps.storage, &canonical_addr);
+        let expected_key = ViewingKey::read_viewing_key(&deps.storage, &canonical_addr)?;
 
-        if expected_key.is_none() {
+        match check_stored_viewing_key(
+            &vk,
+            expected_key,
+            block_time,
+            &[ViewingKeyScope::SellerOrders],
+        ) {
+            Some(_) => Ok(()),
+            None => Err(StdError::generic_err("Authentication failed")),
+        }
+    }
+}
+
+/// Returns the stored key if the given key matches it, hasn't expired and grants any of the scopes
+fn check_stored_viewing_key(
+    key: &ViewingKey,
+    stored_key: Option<StoredViewingKey>,
+    block_time: u64,
+    scopes: &[ViewingKeyScope],
+) -> Option<StoredViewingKey> {
+    match stored_key {
+        None => {
             // Checking the key will take significant time. We don't want to exit immediately if it isn't set
             // in a way which will allow to time the command and determine if a viewing key doesn't exist
-            vk.check_viewing_key(&[0u8; VIEWING_KEY_SIZE]);
-            Err(StdError::generic_err("Authentication failed"))
-        } else if vk.check_viewing_key(expected_key.unwrap().as_slice()) {
-            Ok(())
-        } else {
-            Err(StdError::generic_err("Authentication failed"))
+            key.check_viewing_key(&[0u8; VIEWING_KEY_SIZE]);
+            None
+        }
+        Some(stored_key) if key.check_viewing_key(&stored_key.hashed_key) => {
+            if stored_key.is_expired(block_time)
+                || !scopes.iter().any(|scope| stored_key.has_scope(*scope))
+            {
+                return None;
+            }
+            Some(stored_key)
         }
+        Some(_) => None,
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ItemData {
     pub static_data: StaticItemData,
-    pub current_group_size: u32,
+    /// Hidden from the public catalog if the item has bucketed progress
+    pub current_group_size: Option<u32>,
+    /// Set instead of the group size if it is hidden
+    pub progress_bucket: Option<ProgressBucket>,
+    /// The profile of the seller of the item, if it is still registered
+    pub seller: Option<SellerProfile>,
+}
+
+/// The group is at least `from_percent` and less than `to_percent` percent full
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ProgressBucket {
+    pub from_percent: u8,
+    pub to_percent: u8,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -92,13 +212,285 @@ pub struct UserProductQuantity {
     pub quantity: u32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserItem {
+    pub url: String,
+    pub quantity: u32,
+    /// Always exact, even if the item has bucketed progress
+    pub current_group_size: u32,
+    /// The contact data the seller will get for this item: the override given when joining
+    /// it, or else the user's contact profile
+    pub contact_data: Option<UserContactData>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingStatus {
+    /// The group is still being gathered
+    Open,
+    /// The group size goal was reached and the seller was paid
+    GoalReached,
+    Shipped,
+}
+
+/// An item the user participates in, in any category
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Participation {
+    pub category: String,
+    pub url: String,
+    pub name: String,
+    pub quantity: u32,
+    /// The amount (in SCRT) the user paid for their units
+    pub paid_amount: Uint128,
+    pub status: ListingStatus,
+    /// Always exact. The final group size once the goal was reached
+    pub current_group_size: u32,
+    pub group_size_goal: u32,
+    /// The contact data the seller gets for this item. Removed once the order is shipped
+    pub contact_data: Option<UserContactData>,
+}
+
+/// An item the seller added, in any category
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SellerListing {
+    pub static_data: StaticItemData,
+    pub status: ListingStatus,
+    /// Always exact. The final group size once the goal was reached
+    pub current_group_size: u32,
+    /// The amount (in SCRT) held for the group until the goal is reached
+    pub escrowed_amount: Uint128,
+    /// The seconds left until the deadline as of the latest transaction, if the item has one.
+    /// Like in ListingDetail, it lags behind while nobody transacts with the contract
+    pub time_remaining: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractStatus {
+    Normal,
+    /// No new items, and participants can't join groups or increase their quantity
+    StopNewListingsAndJoins,
+    /// Everything is frozen, except for the admin messages and the emergency withdrawals
+    StopAll,
+}
+
+/// A category of the catalog. Its key identifies it in messages and in storage, and never changes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Category {
+    pub key: String,
+    /// The name to display, which the admin can change
+    pub name: String,
+    /// Disabled categories don't accept new items. Their existing items stay available
+    pub enabled: bool,
+    /// The key of the category this one is a subcategory of
+    pub parent: Option<String>,
+}
+
+/// Running counters of the marketplace, kept per category and globally
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct MarketStats {
+    pub active_listings: u32,
+    /// Listings whose group size goal was reached
+    pub fulfilled_listings: u32,
+    /// The participations in the active listings. Like the units and the escrowed volume, it
+    /// leaves out the listings with bucketed progress, whose exact group size is private
+    pub total_participants: u32,
+    /// The units ordered in the active listings
+    pub total_units: u64,
+    /// The amount (in SCRT) currently held for the active listings
+    pub escrowed_volume: Uint128,
+    /// The amount (in SCRT) paid to sellers so far
+    pub paid_out_volume: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TxAction {
+    /// The user deposited for the units they joined with
+    Join,
+    /// The user deposited for the units they added
+    IncreaseQuantity,
+    /// The user was refunded for the units they dropped
+    DecreaseQuantity,
+    /// The user was refunded for all their units
+    Leave,
+    /// The seller was paid once the group size goal was reached
+    SellerPayout,
+    /// The user was refunded from the seller's bond for returned units
+    ReturnRefund,
+}
+
+/// A movement of funds in a user's history
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Tx {
+    /// The position of the transaction in the user's history, starting at 0
+    pub id: u64,
+    pub action: TxAction,
+    pub category: String,
+    pub url: String,
+    /// The units the transaction is about
+    pub quantity: u32,
+    /// In SCRT
+    pub amount: Uint128,
+    pub block_time: u64,
+}
+
+/// A participant of an item whose group size goal was reached
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderParticipant {
+    pub account_address: HumanAddr,
+    /// Where the refunds of approved returns are sent, instead of the account address. Never
+    /// shown to the seller
+    pub refund_address: Option<HumanAddr>,
+    pub quantity: u32,
+    /// Removed once the seller marks the order as shipped
+    pub contact_data: Option<UserContactData>,
+    /// Set instead of contact_data if the participant sealed it to the seller's public key.
+    /// Also removed once the seller marks the order as shipped
+    pub sealed_contact_data: Option<Binary>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnStatus {
+    /// Waiting for the seller to approve or reject it
+    Requested,
+    /// The buyer was refunded from the seller's bond
+    Approved,
+    /// Rejected by the seller, waiting for the admin to resolve it
+    Disputed,
+    /// Rejected by the admin after a dispute
+    Rejected,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReturnRequest {
+    pub account_address: HumanAddr,
+    pub quantity: u32,
+    pub reason: String,
+    pub requested_at: u64,
+    pub status: ReturnStatus,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum HandleMsg {
-    AddItem(StaticItemData),
+    AddItem(AddItemData),
     UpdateItem(UpdateItemData),
     RemoveItem(RemoveItemData),
-    SetViewingKey { key: String },
+    SetViewingKey {
+        key: String,
+        /// The block time (in seconds) from which the key is rejected. Never expires if not set.
+        /// Queries can't see the current block time and compare it with the one of the latest
+        /// transaction to the contract instead, so an expired key keeps working until the next one
+        expires_at: Option<u64>,
+        /// Grants all the scopes if not set
+        scopes: Option<Vec<ViewingKeyScope>>,
+        padding: Option<String>,
+    },
+    CreateViewingKey {
+        entropy: String,
+        /// Same as in SetViewingKey
+        expires_at: Option<u64>,
+        scopes: Option<Vec<ViewingKeyScope>>,
+        padding: Option<String>,
+    },
+    SetContactProfile {
+        email: String,
+        delivery_address: String,
+        padding: Option<String>,
+    },
+    RegisterSeller {
+        display_name: String,
+        contact_email: String,
+        payout_address: HumanAddr,
+        public_key: Binary,
+        padding: Option<String>,
+    },
+    SetSellerVerified {
+        seller: HumanAddr,
+        verified: bool,
+        padding: Option<String>,
+    },
+    MarkOrderShipped {
+        category: String,
+        url: String,
+        padding: Option<String>,
+    },
+    DepositSellerBond {
+        padding: Option<String>,
+    },
+    /// Sends back part of the sender's bond, in uscrt. The bond open return windows may still
+    /// need stays locked
+    WithdrawSellerBond {
+        amount: Uint128,
+        padding: Option<String>,
+    },
+    RequestReturn {
+        category: String,
+        url: String,
+        quantity: u32,
+        reason: String,
+        padding: Option<String>,
+    },
+    ApproveReturn {
+        category: String,
+        url: String,
+        buyer: HumanAddr,
+        padding: Option<String>,
+    },
+    RejectReturn {
+        category: String,
+        url: String,
+        buyer: HumanAddr,
+        padding: Option<String>,
+    },
+    ResolveReturnDispute {
+        category: String,
+        url: String,
+        buyer: HumanAddr,
+        refund: bool,
+        padding: Option<String>,
+    },
+    RevokePermit {
+        permit_name: String,
+        padding: Option<String>,
+    },
+    AddCategory {
+        key: String,
+        name: String,
+        /// The key of an existing category to nest this one under
+        parent: Option<String>,
+        padding: Option<String>,
+    },
+    RenameCategory {
+        key: String,
+        name: String,
+        padding: Option<String>,
+    },
+    DisableCategory {
+        key: String,
+        padding: Option<String>,
+    },
+    /// Proposes a new admin. The role moves once the proposed address accepts it
+    ChangeAdmin {
+        address: HumanAddr,
+        padding: Option<String>,
+    },
+    AcceptAdmin {
+        padding: Option<String>,
+    },
+    SetContractStatus {
+        status: ContractStatus,
+        padding: Option<String>,
+    },
+    /// Refunds the sender's whole deposit for an item and removes them from its group. Only
+    /// while the contract is stopped
+    EmergencyWithdraw {
+        category: String,
+        url: String,
+        padding: Option<String>,
+    },
 }
 
 /// Responses from handle functions
@@ -109,41 +501,258 @@ pub enum HandleAnswer {
     UpdateItem { status: ResponseStatus },
     RemoveItem { status: ResponseStatus },
     SetViewingKey { status: ResponseStatus },
+    CreateViewingKey { key: ViewingKey },
+    SetContactProfile { status: ResponseStatus },
+    RegisterSeller { status: ResponseStatus },
+    SetSellerVerified { status: ResponseStatus },
+    MarkOrderShipped { status: ResponseStatus },
+    DepositSellerBond { status: ResponseStatus },
+    WithdrawSellerBond { status: ResponseStatus },
+    RequestReturn { status: ResponseStatus },
+    ApproveReturn { status: ResponseStatus },
+    RejectReturn { status: ResponseStatus },
+    ResolveReturnDispute { status: ResponseStatus },
+    RevokePermit { status: ResponseStatus },
+    AddCategory { status: ResponseStatus },
+    RenameCategory { status: ResponseStatus },
+    DisableCategory { status: ResponseStatus },
+    ChangeAdmin { status: ResponseStatus },
+    AcceptAdmin { status: ResponseStatus },
+    SetContractStatus { status: ResponseStatus },
+    EmergencyWithdraw { status: ResponseStatus },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    // Get all the items of a category
-    GetItems {
+    // Get all the items of a category and their group progress. Public
+    // A filter, a sort or the subcategories scan all the items of the category tree on every page
+    Catalog {
         category: String,
+        /// Also lists the items of all the subcategories of the category. Defaults to false
+        include_subcategories: Option<bool>,
+        filter: Option<CatalogFilter>,
+        sort: Option<CatalogSort>,
+        page: Option<u32>,
+        page_size: u32,
+        padding: Option<String>,
+    },
+    // Get the parameters of the contract. Public
+    ContractInfo {
+        padding: Option<String>,
+    },
+    // Get all the categories, including the disabled ones. Public
+    Categories {
+        padding: Option<String>,
+    },
+    // Get the statistics of a category, or of the whole marketplace. Public
+    Stats {
+        category: Option<String>,
+        padding: Option<String>,
+    },
+    // Get a single item. With a viewer, also the viewer's participation in it
+    ListingDetail {
+        category: String,
+        url: String,
+        viewer: Option<ViewerInfo>,
+        padding: Option<String>,
+    },
+    // Get the items of a category the user participates in, and their contact data
+    MyParticipations {
+        category: String,
+        address: HumanAddr,
+        key: String,
+        padding: Option<String>,
+    },
+    // Get every item the user participates in, in all the categories, including reached goals
+    ParticipationDashboard {
         address: HumanAddr,
         key: String,
+        padding: Option<String>,
+    },
+    // Get every item the seller added, in all the categories, including reached goals
+    SellerListings {
+        address: HumanAddr,
+        key: String,
+        page: Option<u32>,
+        page_size: u32,
+        padding: Option<String>,
+    },
+    // Get the user's deposits, refunds and payouts, the latest first
+    TransactionHistory {
+        address: HumanAddr,
+        key: String,
+        page: Option<u32>,
+        page_size: u32,
+        padding: Option<String>,
+    },
+    // Get the participants of an item whose group size goal was reached. Sellers only
+    GetOrderParticipants {
+        category: String,
+        url: String,
+        address: HumanAddr,
+        key: String,
+        page: Option<u32>,
+        page_size: u32,
+        padding: Option<String>,
+    },
+    // Get the return requests of an order. Sellers get all of them, buyers only their own
+    GetReturnRequests {
+        category: String,
+        url: String,
+        address: HumanAddr,
+        key: String,
+        padding: Option<String>,
+    },
+    // Any of the authenticated queries, using a permit signed by the user instead of a viewing key.
+    // The participant's queries (ListingDetail, MyParticipations, ParticipationDashboard) need the
+    // `balance` permission, the seller's queries and the histories (SellerListings,
+    // TransactionHistory, GetOrderParticipants, GetReturnRequests) the `history` one. `owner`
+    // grants them all
+    WithPermit {
+        permit: Permit,
+        query: QueryWithPermit,
+        padding: Option<String>,
+    },
+}
+
+/// Every given field must match for an item to be included
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CatalogFilter {
+    /// Case-insensitive part of the item's name
+    pub name: Option<String>,
+    /// Applies to the price participants pay (the wanted price)
+    pub min_price: Option<Uint128>,
+    pub max_price: Option<Uint128>,
+    /// Uses the lower bound of the bucket for items with bucketed progress
+    pub min_progress_percent: Option<u8>,
+    pub seller: Option<HumanAddr>,
+    /// Only items whose deadline is within this many seconds
+    pub ending_within: Option<u64>,
+    /// Case-insensitive tag the item must have
+    pub tag: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogSort {
+    Newest,
+    PriceAscending,
+    PriceDescending,
+    /// The fullest groups first
+    Progress,
+    /// The closest deadlines first. Items without a deadline come last
+    Deadline,
+}
+
+/// Authenticates the private parts of a public query
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ViewerInfo {
+    pub address: HumanAddr,
+    pub key: String,
+}
+
+/// The queries that can be authenticated with a permit. The permit needs the permission noted
+/// on each query, or the `owner` permission which grants all of them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryWithPermit {
+    /// Requires the `balance` permission
+    ListingDetail { category: String, url: String },
+    /// Requires the `balance` permission
+    MyParticipations { category: String },
+    /// Requires the `balance` permission
+    ParticipationDashboard {},
+    /// Requires the `history` permission
+    SellerListings { page: Option<u32>, page_size: u32 },
+    /// Requires the `history` permission
+    TransactionHistory { page: Option<u32>, page_size: u32 },
+    /// Requires the `history` permission
+    GetOrderParticipants {
+        category: String,
+        url: String,
+        page: Option<u32>,
+        page_size: u32,
     },
+    /// Requires the `history` permission
+    GetReturnRequests { category: String, url: String },
 }
 
 impl QueryMsg {
+    /// The viewing key of the query must grant any of these scopes
+    fn required_scopes(&self) -> &'static [ViewingKeyScope] {
+        match self {
+            QueryMsg::MyParticipations { .. } | QueryMsg::ParticipationDashboard { .. } => {
+                &[ViewingKeyScope::Participations]
+            }
+            QueryMsg::ListingDetail { .. } => &[
+                ViewingKeyScope::Participations,
+                ViewingKeyScope::SellerOrders,
+            ],
+            QueryMsg::SellerListings { .. } | QueryMsg::GetOrderParticipants { .. } => {
+                &[ViewingKeyScope::SellerOrders]
+            }
+            QueryMsg::GetReturnRequests { .. } | QueryMsg::TransactionHistory { .. } => &[
+                ViewingKeyScope::Participations,
+                ViewingKeyScope::SellerOrders,
+            ],
+            QueryMsg::Catalog { .. }
+            | QueryMsg::ContractInfo { .. }
+            | QueryMsg::Categories { .. }
+            | QueryMsg::Stats { .. }
+            | QueryMsg::WithPermit { .. } => &[],
+        }
+    }
+
     pub fn authenticate<S: Storage, A: Api, Q: Querier>(
         &self,
         deps: &Extern<S, A, Q>,
-    ) -> StdResult<HumanAddr> {
+    ) -> StdResult<(HumanAddr, StoredViewingKey)> {
         let (address, key) = match self {
-            QueryMsg::GetItems { address, key, .. } => (address.clone(), ViewingKey(key.clone())),
+            QueryMsg::ListingDetail {
+                viewer: Some(viewer),
+                ..
+            } => (viewer.address.clone(), ViewingKey(viewer.key.clone())),
+            QueryMsg::MyParticipations { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::ParticipationDashboard { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::SellerListings { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::TransactionHistory { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::GetOrderParticipants { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::GetReturnRequests { address, key, .. } => {
+                (address.clone(), ViewingKey(key.clone()))
+            }
+            QueryMsg::Catalog { .. }
+            | QueryMsg::ContractInfo { .. }
+            | QueryMsg::Categories { .. }
+            | QueryMsg::Stats { .. }
+            | QueryMsg::ListingDetail { viewer: None, .. } => {
+                return Err(StdError::generic_err("The catalog is public"))
+            }
+            QueryMsg::WithPermit { .. } => {
+                return Err(StdError::generic_err(
+                    "Permit queries are not authenticated with a viewing key",
+                ))
+            }
         };
 
         let canonical_addr = deps.api.canonical_address(&address)?;
 
-        let expected_key = ViewingKey::read_viewing_key(&deps.storage, &canonical_addr);
+        let expected_key = ViewingKey::read_viewing_key(&deps.storage, &canonical_addr)?;
+        let block_time = load_block_time(&deps.storage)?;
 
-        if expected_key.is_none() {
-            // Checking the key will take significant time. We don't want to exit immediately if it isn't set
-            // in a way which will allow to time the command and determine if a viewing key doesn't exist
-            key.check_viewing_key(&[0u8; VIEWING_KEY_SIZE]);
-            Err(StdError::generic_err("Wrong viewing key"))
-        } else if key.check_viewing_key(expected_key.unwrap().as_slice()) {
-            Ok(address)
-        } else {
-            Err(StdError::generic_err("Wrong viewing key"))
+        match check_stored_viewing_key(&key, expected_key, block_time, self.required_scopes()) {
+            Some(stored_key) => Ok((address, stored_key)),
+            None => Err(StdError::generic_err("Wrong viewing key")),
         }
     }
 }
@@ -152,16 +761,130 @@ impl QueryMsg {
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryAnswer {
-    GetItems(GetItems),
+    Catalog(Catalog),
+    ContractInfo(ContractInfo),
+    Categories(Categories),
+    Stats(Stats),
+    ListingDetail(Box<ListingDetail>),
+    MyParticipations(MyParticipations),
+    ParticipationDashboard(ParticipationDashboard),
+    SellerListings(SellerListings),
+    TransactionHistory(TransactionHistory),
+    GetOrderParticipants(GetOrderParticipants),
+    GetReturnRequests(GetReturnRequests),
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
-pub struct GetItems {
-    /// The list of items of the category (provided in QueryMsg::GetItems::category)
+pub struct Catalog {
+    /// The requested page of the items of the category (provided in QueryMsg::Catalog::category)
     pub items: Vec<ItemData>,
+    /// The total number of items in the category
+    pub total: u32,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ContractInfo {
+    pub admin: HumanAddr,
+    /// The address proposed as the next admin, if any
+    pub pending_admin: Option<HumanAddr>,
+    pub contract_status: ContractStatus,
+    /// The denom of the funds. Prices are in its whole unit (SCRT for uscrt)
+    pub denom: String,
+    /// The categories items can be added to
+    pub categories: Vec<String>,
+    pub return_window: u64,
+    pub fee_bps: u16,
+    pub fee_recipient: HumanAddr,
+    pub max_page_size: u32,
+    /// The version of the contract's code
+    pub version: String,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Categories {
+    pub categories: Vec<Category>,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Stats {
+    /// The category the statistics are of. None for the whole marketplace
+    pub category: Option<String>,
+    pub stats: MarketStats,
+    pub status: ResponseStatus,
+}
+
+/// The price per unit from a certain group size
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceTier {
+    pub group_size: u32,
+    pub price: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ListingDetail {
+    /// The group size is exact for the seller and the participants of the item
+    pub item: ItemData,
+    /// The seconds left until the deadline as of the latest transaction, if the item has one.
+    /// Queries can't see the current block time, so this overstates the time left while nobody
+    /// transacts with the contract
+    pub time_remaining: Option<u64>,
+    /// The retail price, then the price once the group size goal is reached
+    pub price_tiers: Vec<PriceTier>,
+    /// The viewer's participation in the item, if any
+    pub participation: Option<UserItem>,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct MyParticipations {
     /// The list of items the user participates in, if any
-    pub user_items: Vec<UserProductQuantity>,
-    /// The contact data the user added when applied to an item
+    pub user_items: Vec<UserItem>,
+    /// The contact profile of the user, if they set one
     pub contact_data: Option<UserContactData>,
     pub status: ResponseStatus,
 }
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ParticipationDashboard {
+    /// The open items come first, then those whose group size goal was reached
+    pub participations: Vec<Participation>,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct SellerListings {
+    /// The requested page of the seller's items
+    pub listings: Vec<SellerListing>,
+    /// The total number of the seller's items, including reached goals
+    pub total: u32,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct TransactionHistory {
+    /// The requested page of the user's transactions, the latest first
+    pub txs: Vec<Tx>,
+    /// The total number of the user's transactions
+    pub total: u32,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct GetOrderParticipants {
+    /// The requested page of the order's participants
+    pub participants: Vec<OrderParticipant>,
+    /// The total number of participants in the order
+    pub total: u32,
+    /// The block time in which the seller marked the order as shipped, if they did
+    pub shipped_at: Option<u64>,
+    pub status: ResponseStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct GetReturnRequests {
+    pub return_requests: Vec<ReturnRequest>,
+    pub status: ResponseStatus,
+}
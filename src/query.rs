@@ -1,90 +1,832 @@
-use secret_toolkit::crypto::sha_256;
+use std::cmp::Reverse;
+
+use secret_toolkit::{
+    crypto::sha_256,
+    permit::{validate, Permission, Permit},
+    utils::pad_query_result,
+};
 
 use crate::{
-    msg::{GetItems, ItemData, QueryMsg, ResponseStatus},
+    msg::{
+        Catalog, CatalogFilter, CatalogSort, Categories, ContractInfo, GetOrderParticipants,
+        GetReturnRequests, ItemData, ListingDetail, ListingStatus, MyParticipations, Participation,
+        ParticipationDashboard, PriceTier, ProgressBucket, QueryMsg, QueryWithPermit,
+        ResponseStatus, SellerListing, SellerListings, StaticItemData, Stats, TransactionHistory,
+        UserContactData, UserItem,
+    },
     state::{
-        get_category_item_group_size, get_category_item_user_details, get_category_items,
-        get_category_prefixes, get_ctegory_user_items_quantities,
+        get_category_item_by_url, get_category_item_group_size, get_category_item_user_details,
+        get_category_items, get_category_items_page, get_category_keys, get_category_prefixes,
+        get_category_tree_keys, get_category_user_items_quantities_by_url,
+        get_ctegory_user_items_quantities, get_order_participant, get_order_participants,
+        get_page_start, get_participation_contact_data, get_return_requests,
+        get_seller_listing_refs, get_txs, get_user_orders, load_block_time, load_categories,
+        load_config, load_stats, may_load_contact_profile, may_load_order, may_load_seller_profile,
+        order_key, PREFIX_REVOKED_PERMITS,
     },
+    update_logic::DENOM,
+    utils::BLOCK_SIZE,
+    viewing_key::ViewingKeyScope,
 };
 use cosmwasm_std::{
-    to_binary, Api, Binary, Extern, HumanAddr, Querier, QueryResult, StdResult, Storage,
+    to_binary, Api, Binary, Extern, HumanAddr, Querier, QueryResult, StdError, StdResult, Storage,
+    Uint128,
 };
 
+const PROGRESS_BUCKET_PERCENT: u8 = 25;
+
 pub fn query<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
 ) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetItems { .. } => viewing_keys_queries(deps, msg),
+    let response = match msg {
+        QueryMsg::Catalog {
+            category,
+            include_subcategories,
+            filter,
+            sort,
+            page,
+            page_size,
+            ..
+        } => to_binary(&get_catalog(
+            deps,
+            &category,
+            include_subcategories.unwrap_or(false),
+            filter,
+            sort,
+            page.unwrap_or(0),
+            page_size,
+        )?),
+        QueryMsg::ContractInfo { .. } => to_binary(&get_contract_info(deps)?),
+        QueryMsg::Categories { .. } => to_binary(&Categories {
+            categories: load_categories(&deps.storage)?,
+            status: ResponseStatus::Success,
+        }),
+        QueryMsg::Stats { category, .. } => to_binary(&get_stats(deps, category)?),
+        QueryMsg::ListingDetail {
+            category,
+            url,
+            viewer: None,
+            ..
+        } => to_binary(&get_listing_detail(deps, &category, &url, None)?),
+        QueryMsg::ListingDetail { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::MyParticipations { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::ParticipationDashboard { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::SellerListings { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::TransactionHistory { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::GetOrderParticipants { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::GetReturnRequests { .. } => viewing_keys_queries(deps, msg),
+        QueryMsg::WithPermit { permit, query, .. } => permit_queries(deps, permit, query),
+    };
+    pad_query_result(response, BLOCK_SIZE)
+}
+
+fn permit_queries<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    permit: Permit,
+    query: QueryWithPermit,
+) -> QueryResult {
+    let config = load_config(&deps.storage)?;
+    // Validate the permit and get the address of its signer
+    let account = validate(
+        deps,
+        PREFIX_REVOKED_PERMITS,
+        &permit,
+        config.contract_address,
+    )?;
+
+    match query {
+        QueryWithPermit::ListingDetail { category, url } => {
+            check_permit_permission(&permit, Permission::Balance)?;
+            to_binary(&get_listing_detail(
+                deps,
+                &category,
+                &url,
+                Some((&account, true)),
+            )?)
+        }
+        QueryWithPermit::MyParticipations { category } => {
+            check_permit_permission(&permit, Permission::Balance)?;
+            to_binary(&get_my_participations(deps, &account, &category, true)?)
+        }
+        QueryWithPermit::ParticipationDashboard {} => {
+            check_permit_permission(&permit, Permission::Balance)?;
+            to_binary(&get_participation_dashboard(deps, &account, true)?)
+        }
+        QueryWithPermit::SellerListings { page, page_size } => {
+            check_permit_permission(&permit, Permission::History)?;
+            to_binary(&get_seller_listings(
+                deps,
+                &account,
+                page.unwrap_or(0),
+                page_size,
+            )?)
+        }
+        QueryWithPermit::TransactionHistory { page, page_size } => {
+            check_permit_permission(&permit, Permission::History)?;
+            to_binary(&get_transaction_history(
+                deps,
+                &account,
+                page.unwrap_or(0),
+                page_size,
+            )?)
+        }
+        QueryWithPermit::GetOrderParticipants {
+            category,
+            url,
+            page,
+            page_size,
+        } => {
+            check_permit_permission(&permit, Permission::History)?;
+            to_binary(&get_seller_order_participants(
+                deps,
+                &account,
+                &category,
+                &url,
+                page.unwrap_or(0),
+                page_size,
+            )?)
+        }
+        QueryWithPermit::GetReturnRequests { category, url } => {
+            check_permit_permission(&permit, Permission::History)?;
+            to_binary(&get_order_return_requests(deps, &account, &category, &url)?)
+        }
+    }
+}
+
+fn check_permit_permission(permit: &Permit, permission: Permission) -> StdResult<()> {
+    if permit.check_permission(&Permission::Owner) || permit.check_permission(&permission) {
+        return Ok(());
     }
+    Err(StdError::generic_err(format!(
+        "This permit doesn't have the {:?} or Owner permission",
+        permission
+    )))
 }
 
 pub fn viewing_keys_queries<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     msg: QueryMsg,
 ) -> QueryResult {
-    msg.authenticate(deps)?;
+    let (_address, viewing_key) = msg.authenticate(deps)?;
 
     match msg {
-        QueryMsg::GetItems {
+        QueryMsg::ListingDetail {
+            category,
+            url,
+            viewer: Some(viewer),
+            ..
+        } => to_binary(&get_listing_detail(
+            deps,
+            &category,
+            &url,
+            Some((
+                &viewer.address,
+                viewing_key.has_scope(ViewingKeyScope::ContactData),
+            )),
+        )?),
+        QueryMsg::MyParticipations {
             address, category, ..
-        } => to_binary(&may_get_items(deps, &address, category)?),
+        } => to_binary(&get_my_participations(
+            deps,
+            &address,
+            &category,
+            viewing_key.has_scope(ViewingKeyScope::ContactData),
+        )?),
+        QueryMsg::ParticipationDashboard { address, .. } => {
+            to_binary(&get_participation_dashboard(
+                deps,
+                &address,
+                viewing_key.has_scope(ViewingKeyScope::ContactData),
+            )?)
+        }
+        QueryMsg::SellerListings {
+            address,
+            page,
+            page_size,
+            ..
+        } => to_binary(&get_seller_listings(
+            deps,
+            &address,
+            page.unwrap_or(0),
+            page_size,
+        )?),
+        QueryMsg::TransactionHistory {
+            address,
+            page,
+            page_size,
+            ..
+        } => to_binary(&get_transaction_history(
+            deps,
+            &address,
+            page.unwrap_or(0),
+            page_size,
+        )?),
+        QueryMsg::GetOrderParticipants {
+            category,
+            url,
+            address,
+            page,
+            page_size,
+            ..
+        } => to_binary(&get_seller_order_participants(
+            deps,
+            &address,
+            &category,
+            &url,
+            page.unwrap_or(0),
+            page_size,
+        )?),
+        QueryMsg::GetReturnRequests {
+            category,
+            url,
+            address,
+            ..
+        } => to_binary(&get_order_return_requests(deps, &address, &category, &url)?),
+        QueryMsg::Catalog { .. }
+        | QueryMsg::ContractInfo { .. }
+        | QueryMsg::Categories { .. }
+        | QueryMsg::Stats { .. }
+        | QueryMsg::ListingDetail { viewer: None, .. }
+        | QueryMsg::WithPermit { .. } => {
+            unreachable!("Public and permit queries are handled separately")
+        }
     }
 }
 
-pub fn may_get_items<S: Storage, A: Api, Q: Querier>(
+pub fn get_order_return_requests<S: Storage, A: Api, Q: Querier>(
     deps: &Extern<S, A, Q>,
     account: &HumanAddr,
-    category: String,
-) -> StdResult<GetItems> {
-    let address = deps.api.canonical_address(account)?;
+    category: &str,
+    url: &str,
+) -> StdResult<GetReturnRequests> {
+    let order_key = order_key(category, url);
+    let order = match may_load_order(&deps.storage, &order_key)? {
+        Some(order) => order,
+        None => return Err(StdError::generic_err("No such order")),
+    };
+
+    let mut return_requests = get_return_requests(&deps.storage, &order_key)?;
+    // Buyers may only see their own return requests
+    if order.static_data.seller_address != account.to_string() {
+        return_requests.retain(|return_request| return_request.account_address == *account);
+    }
+
+    Ok(GetReturnRequests {
+        return_requests,
+        status: ResponseStatus::Success,
+    })
+}
+
+pub fn get_seller_order_participants<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    category: &str,
+    url: &str,
+    page: u32,
+    page_size: u32,
+) -> StdResult<GetOrderParticipants> {
+    check_page_size(deps, page_size)?;
+    let order_key = order_key(category, url);
+    let order = match may_load_order(&deps.storage, &order_key)? {
+        Some(order) => order,
+        None => return Err(StdError::generic_err("No such order")),
+    };
+    if order.static_data.seller_address != account.to_string() {
+        return Err(StdError::unauthorized());
+    }
+
+    let (mut participants, total) =
+        get_order_participants(&deps.storage, &order_key, page, page_size)?;
+    for participant in participants.iter_mut() {
+        participant.refund_address = None;
+    }
+    Ok(GetOrderParticipants {
+        participants,
+        total,
+        shipped_at: order.shipped_at,
+        status: ResponseStatus::Success,
+    })
+}
+
+pub fn get_contract_info<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+) -> StdResult<ContractInfo> {
+    let config = load_config(&deps.storage)?;
+    Ok(ContractInfo {
+        admin: config.admin,
+        pending_admin: config.pending_admin,
+        contract_status: config.status,
+        denom: DENOM.to_string(),
+        categories: load_categories(&deps.storage)?
+            .into_iter()
+            .filter(|category| category.enabled)
+            .map(|category| category.key)
+            .collect(),
+        return_window: config.return_window,
+        fee_bps: config.fee_bps,
+        fee_recipient: config.fee_recipient,
+        max_page_size: config.max_page_size,
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        status: ResponseStatus::Success,
+    })
+}
+
+fn check_page_size<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    page_size: u32,
+) -> StdResult<()> {
+    let max_page_size = load_config(&deps.storage)?.max_page_size;
+    if page_size > max_page_size {
+        return Err(StdError::generic_err(format!(
+            "The page size can't exceed {}",
+            max_page_size
+        )));
+    }
+    Ok(())
+}
+
+pub fn get_stats<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    category: Option<String>,
+) -> StdResult<Stats> {
+    if let Some(category) = &category {
+        get_category_prefixes(&deps.storage, category)?;
+    }
+    Ok(Stats {
+        stats: load_stats(&deps.storage, category.as_deref())?,
+        category,
+        status: ResponseStatus::Success,
+    })
+}
+
+pub fn get_catalog<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    category: &str,
+    include_subcategories: bool,
+    filter: Option<CatalogFilter>,
+    sort: Option<CatalogSort>,
+    page: u32,
+    page_size: u32,
+) -> StdResult<Catalog> {
+    check_page_size(deps, page_size)?;
+    let (static_prefix, _dynamic_prefix, _dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, category)?;
+    let categories = match include_subcategories {
+        true => get_category_tree_keys(&deps.storage, category)?,
+        false => vec![category.to_string()],
+    };
+    if categories.len() == 1 && filter.is_none() && sort.is_none() {
+        let (items_static_data, total) =
+            get_category_items_page(&deps.storage, &static_prefix, page, page_size)?;
+        return Ok(Catalog {
+            items: get_items_data(deps, category, items_static_data, false)?,
+            total,
+            status: ResponseStatus::Success,
+        });
+    }
+
+    // Filtering, sorting and subcategories need all the items, so the whole category tree is
+    // scanned on every page. The query gas limit of the node bounds the size of that scan
+    let page_start = get_page_start(page, page_size)?;
+    let mut items_data = Vec::new();
+    for category in categories {
+        let (static_prefix, _dynamic_prefix, _dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, &category)?;
+        let items_static_data = get_category_items(&deps.storage, &static_prefix)?;
+        items_data.extend(get_items_data(deps, &category, items_static_data, false)?);
+    }
+    if let Some(filter) = filter {
+        let block_time = load_block_time(&deps.storage)?;
+        items_data.retain(|item_data| matches_catalog_filter(item_data, &filter, block_time));
+    }
+    match sort {
+        Some(CatalogSort::Newest) => {
+            items_data.sort_by_key(|item_data| Reverse(item_data.static_data.created_at))
+        }
+        Some(CatalogSort::PriceAscending) => {
+            items_data.sort_by_key(|item_data| item_data.static_data.wanted_price)
+        }
+        Some(CatalogSort::PriceDescending) => {
+            items_data.sort_by_key(|item_data| Reverse(item_data.static_data.wanted_price))
+        }
+        Some(CatalogSort::Progress) => {
+            items_data.sort_by_key(|item_data| Reverse(get_item_progress_percent(item_data)))
+        }
+        Some(CatalogSort::Deadline) => {
+            items_data.sort_by_key(|item_data| item_data.static_data.deadline.unwrap_or(u64::MAX))
+        }
+        None => {}
+    }
+
+    let total = items_data.len() as u32;
+    let items = items_data
+        .into_iter()
+        .skip(page_start)
+        .take(page_size as _)
+        .collect();
+    Ok(Catalog {
+        items,
+        total,
+        status: ResponseStatus::Success,
+    })
+}
+
+fn matches_catalog_filter(item_data: &ItemData, filter: &CatalogFilter, block_time: u64) -> bool {
+    let static_data = &item_data.static_data;
+    if let Some(name) = &filter.name {
+        if !static_data
+            .name
+            .to_lowercase()
+            .contains(&name.to_lowercase())
+        {
+            return false;
+        }
+    }
+    if let Some(min_price) = filter.min_price {
+        if static_data.wanted_price < min_price {
+            return false;
+        }
+    }
+    if let Some(max_price) = filter.max_price {
+        if static_data.wanted_price > max_price {
+            return false;
+        }
+    }
+    if let Some(min_progress_percent) = filter.min_progress_percent {
+        if get_item_progress_percent(item_data) < min_progress_percent {
+            return false;
+        }
+    }
+    if let Some(seller) = &filter.seller {
+        if static_data.seller_address != seller.to_string() {
+            return false;
+        }
+    }
+    if let Some(tag) = &filter.tag {
+        if !static_data.tags.contains(&tag.trim().to_lowercase()) {
+            return false;
+        }
+    }
+    if let Some(ending_within) = filter.ending_within {
+        match static_data.deadline {
+            Some(deadline) if deadline > block_time && deadline - block_time <= ending_within => {}
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Doesn't reveal more than the item shows publicly
+fn get_item_progress_percent(item_data: &ItemData) -> u8 {
+    match (&item_data.current_group_size, &item_data.progress_bucket) {
+        (Some(current_group_size), _) => {
+            get_progress_percent(*current_group_size, item_data.static_data.group_size_goal)
+        }
+        (None, Some(progress_bucket)) => progress_bucket.from_percent,
+        (None, None) => 0,
+    }
+}
 
+/// The viewer, if any, is given along with whether it may see its contact data
+pub fn get_listing_detail<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    category: &str,
+    url: &str,
+    viewer: Option<(&HumanAddr, bool)>,
+) -> StdResult<ListingDetail> {
     let (static_prefix, dynamic_prefix, dynamic_prefix_users) =
-        get_category_prefixes(category.as_bytes())?;
-    let items_static_data = get_category_items(&deps.storage, static_prefix)?;
+        get_category_prefixes(&deps.storage, category)?;
+    let item_static_data = match get_category_item_by_url(&deps.storage, &static_prefix, url)? {
+        Some(item_static_data) => item_static_data,
+        None => return Err(StdError::generic_err("No such item")),
+    };
+    let key = sha_256(base64::encode(url).as_bytes());
+
+    let mut participation = None;
+    let mut is_seller = false;
+    if let Some((account, include_contact_data)) = viewer {
+        is_seller = item_static_data.seller_address == account.to_string();
+        let address = deps.api.canonical_address(account)?;
+        if let Some(user_item_quantity) = get_category_user_items_quantities_by_url(
+            &deps.storage,
+            &dynamic_prefix,
+            address.as_slice(),
+            url,
+        )? {
+            participation = Some(UserItem {
+                url: user_item_quantity.url,
+                quantity: user_item_quantity.quantity,
+                current_group_size: (get_category_item_group_size(
+                    &deps.storage,
+                    &dynamic_prefix,
+                    &key,
+                )?)
+                .unwrap_or(0),
+                contact_data: match include_contact_data {
+                    true => get_user_item_contact_data(deps, &dynamic_prefix_users, &key, account)?,
+                    false => None,
+                },
+            });
+        }
+    }
+
+    let block_time = load_block_time(&deps.storage)?;
+    let time_remaining = item_static_data
+        .deadline
+        .map(|deadline| deadline.saturating_sub(block_time));
+    let price_tiers = vec![
+        PriceTier {
+            group_size: 1,
+            price: item_static_data.price,
+        },
+        PriceTier {
+            group_size: item_static_data.group_size_goal,
+            price: item_static_data.wanted_price,
+        },
+    ];
+    let exact_progress = is_seller || participation.is_some();
+    let item = get_items_data(deps, category, vec![item_static_data], exact_progress)?.remove(0);
+
+    Ok(ListingDetail {
+        item,
+        time_remaining,
+        price_tiers,
+        participation,
+        status: ResponseStatus::Success,
+    })
+}
+
+pub fn get_seller_listings<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<SellerListings> {
+    check_page_size(deps, page_size)?;
+    let address = deps.api.canonical_address(account)?;
+    let (listing_refs, total) = get_seller_listing_refs(&deps.storage, &address, page, page_size)?;
+    let block_time = load_block_time(&deps.storage)?;
+
+    let mut listings = Vec::new();
+    for listing_ref in listing_refs {
+        let (static_prefix, dynamic_prefix, _dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, &listing_ref.category)?;
+        let listing =
+            match get_category_item_by_url(&deps.storage, &static_prefix, &listing_ref.url)? {
+                Some(static_data) => {
+                    let key = sha_256(base64::encode(listing_ref.url.clone()).as_bytes());
+                    let current_group_size =
+                        (get_category_item_group_size(&deps.storage, &dynamic_prefix, &key)?)
+                            .unwrap_or(0);
+                    SellerListing {
+                        status: ListingStatus::Open,
+                        current_group_size,
+                        escrowed_amount: Uint128(
+                            current_group_size as u128 * static_data.wanted_price.u128(),
+                        ),
+                        time_remaining: static_data
+                            .deadline
+                            .map(|deadline| deadline.saturating_sub(block_time)),
+                        static_data,
+                    }
+                }
+                None => {
+                    let order_key = order_key(&listing_ref.category, &listing_ref.url);
+                    let order = match may_load_order(&deps.storage, &order_key)? {
+                        Some(order) => order,
+                        None => continue,
+                    };
+                    SellerListing {
+                        static_data: order.static_data,
+                        status: match order.shipped_at {
+                            Some(_) => ListingStatus::Shipped,
+                            None => ListingStatus::GoalReached,
+                        },
+                        current_group_size: order.total_quantity,
+                        escrowed_amount: Uint128(0),
+                        time_remaining: None,
+                    }
+                }
+            };
+        listings.push(listing);
+    }
+
+    Ok(SellerListings {
+        listings,
+        total,
+        status: ResponseStatus::Success,
+    })
+}
+
+pub fn get_transaction_history<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<TransactionHistory> {
+    check_page_size(deps, page_size)?;
+    let address = deps.api.canonical_address(account)?;
+    let (txs, total) = get_txs(&deps.storage, &address, page, page_size)?;
+    Ok(TransactionHistory {
+        txs,
+        total,
+        status: ResponseStatus::Success,
+    })
+}
+
+/// Adds the group progress and the seller profile to the items. The seller sees the exact group
+/// size of their items, while the public sees the bucketed progress if the item asks for it
+fn get_items_data<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    category: &str,
+    items_static_data: Vec<StaticItemData>,
+    exact_progress: bool,
+) -> StdResult<Vec<ItemData>> {
+    let (_static_prefix, dynamic_prefix, _dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, category)?;
 
     let mut items_data = Vec::new();
-    for item_static_data in items_static_data.iter() {
+    for item_static_data in items_static_data {
         let key = sha_256(base64::encode(item_static_data.url.clone()).as_bytes());
         let current_group_size =
-            (get_category_item_group_size(&deps.storage, dynamic_prefix, &key)?).unwrap_or(0);
+            (get_category_item_group_size(&deps.storage, &dynamic_prefix, &key)?).unwrap_or(0);
+        let seller_canonical_address = deps
+            .api
+            .canonical_address(&HumanAddr(item_static_data.seller_address.clone()))?;
+        let seller = may_load_seller_profile(&deps.storage, &seller_canonical_address)?;
+        let (current_group_size, progress_bucket) =
+            match !exact_progress && item_static_data.bucketed_progress {
+                true => (
+                    None,
+                    Some(get_progress_bucket(
+                        current_group_size,
+                        item_static_data.group_size_goal,
+                    )),
+                ),
+                false => (Some(current_group_size), None),
+            };
         items_data.push(ItemData {
-            static_data: item_static_data.clone(),
+            static_data: item_static_data,
             current_group_size,
+            progress_bucket,
+            seller,
         })
     }
+    Ok(items_data)
+}
 
-    let user_items =
-        get_ctegory_user_items_quantities(&deps.storage, dynamic_prefix, address.as_slice())?;
+fn get_progress_percent(current_group_size: u32, group_size_goal: u32) -> u8 {
+    (current_group_size as u64 * 100 / group_size_goal.max(1) as u64).min(100) as u8
+}
 
-    if let Some(user_items_iter) = user_items.get(0) {
-        let key = sha_256(base64::encode(user_items_iter.url.clone()).as_bytes());
+fn get_progress_bucket(current_group_size: u32, group_size_goal: u32) -> ProgressBucket {
+    let percent = get_progress_percent(current_group_size, group_size_goal);
+    let from_percent = (percent / PROGRESS_BUCKET_PERCENT * PROGRESS_BUCKET_PERCENT).min(75);
+    ProgressBucket {
+        from_percent,
+        to_percent: from_percent + PROGRESS_BUCKET_PERCENT,
+    }
+}
 
-        let contact_data = match get_category_item_user_details(
-            &deps.storage,
-            dynamic_prefix_users,
-            &key,
-            account,
-        )? {
-            Some(v) => Some(v.contact_data),
-            None => None,
-        };
+pub fn get_my_participations<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    category: &str,
+    include_contact_data: bool,
+) -> StdResult<MyParticipations> {
+    let address = deps.api.canonical_address(account)?;
 
-        let result = GetItems {
-            items: items_data,
-            user_items,
-            contact_data,
-            status: ResponseStatus::Success,
+    let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, category)?;
+    let user_items_quantities =
+        get_ctegory_user_items_quantities(&deps.storage, &dynamic_prefix, address.as_slice())?;
+
+    let mut user_items = Vec::new();
+    for user_item_quantity in user_items_quantities {
+        let key = sha_256(base64::encode(user_item_quantity.url.clone()).as_bytes());
+        let contact_data = match include_contact_data {
+            true => get_user_item_contact_data(deps, &dynamic_prefix_users, &key, account)?,
+            false => None,
         };
-        return Ok(result);
+        let current_group_size =
+            (get_category_item_group_size(&deps.storage, &dynamic_prefix, &key)?).unwrap_or(0);
+        user_items.push(UserItem {
+            url: user_item_quantity.url,
+            quantity: user_item_quantity.quantity,
+            current_group_size,
+            contact_data,
+        })
     }
 
-    let result = GetItems {
-        items: items_data,
+    let result = MyParticipations {
         user_items,
-        contact_data: None,
+        contact_data: match include_contact_data {
+            true => may_load_contact_profile(&deps.storage, &address)?,
+            false => None,
+        },
         status: ResponseStatus::Success,
     };
     Ok(result)
 }
+
+pub fn get_participation_dashboard<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    account: &HumanAddr,
+    include_contact_data: bool,
+) -> StdResult<ParticipationDashboard> {
+    let address = deps.api.canonical_address(account)?;
+    let mut participations = Vec::new();
+
+    for category in get_category_keys(&deps.storage)? {
+        let (static_prefix, dynamic_prefix, dynamic_prefix_users) =
+            get_category_prefixes(&deps.storage, &category)?;
+        let user_items_quantities =
+            get_ctegory_user_items_quantities(&deps.storage, &dynamic_prefix, address.as_slice())?;
+        for user_item_quantity in user_items_quantities {
+            let item_data = match get_category_item_by_url(
+                &deps.storage,
+                &static_prefix,
+                &user_item_quantity.url,
+            )? {
+                Some(item_data) => item_data,
+                None => continue,
+            };
+            let key = sha_256(base64::encode(user_item_quantity.url.clone()).as_bytes());
+            let contact_data = match include_contact_data {
+                true => get_user_item_contact_data(deps, &dynamic_prefix_users, &key, account)?,
+                false => None,
+            };
+            participations.push(Participation {
+                category: category.clone(),
+                url: user_item_quantity.url,
+                name: item_data.name,
+                quantity: user_item_quantity.quantity,
+                paid_amount: Uint128(
+                    user_item_quantity.quantity as u128 * item_data.wanted_price.u128(),
+                ),
+                status: ListingStatus::Open,
+                current_group_size: (get_category_item_group_size(
+                    &deps.storage,
+                    &dynamic_prefix,
+                    &key,
+                )?)
+                .unwrap_or(0),
+                group_size_goal: item_data.group_size_goal,
+                contact_data,
+            })
+        }
+    }
+
+    for order_key in get_user_orders(&deps.storage, &address)? {
+        let order = match may_load_order(&deps.storage, &order_key)? {
+            Some(order) => order,
+            None => continue,
+        };
+        let participant = match get_order_participant(&deps.storage, &order_key, account)? {
+            Some(participant) => participant,
+            None => continue,
+        };
+        let status = match order.shipped_at {
+            Some(_) => ListingStatus::Shipped,
+            None => ListingStatus::GoalReached,
+        };
+        participations.push(Participation {
+            category: order.static_data.category,
+            url: order.static_data.url,
+            name: order.static_data.name,
+            quantity: participant.quantity,
+            paid_amount: Uint128(
+                participant.quantity as u128 * order.static_data.wanted_price.u128(),
+            ),
+            status,
+            current_group_size: order.total_quantity,
+            group_size_goal: order.static_data.group_size_goal,
+            contact_data: match include_contact_data {
+                true => participant.contact_data,
+                false => None,
+            },
+        })
+    }
+
+    Ok(ParticipationDashboard {
+        participations,
+        status: ResponseStatus::Success,
+    })
+}
+
+/// The contact data the seller will get for the user's participation in an item
+fn get_user_item_contact_data<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    dynamic_prefix_users: &[u8],
+    key: &[u8],
+    account: &HumanAddr,
+) -> StdResult<Option<UserContactData>> {
+    let address = deps.api.canonical_address(account)?;
+    match get_category_item_user_details(&deps.storage, dynamic_prefix_users, key, account)? {
+        Some(user_details) => {
+            get_participation_contact_data(&deps.storage, &address, &user_details)
+        }
+        None => Ok(None),
+    }
+}
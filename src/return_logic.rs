@@ -0,0 +1,378 @@
+use crate::{
+    handle::check_admin,
+    msg::{HandleAnswer, ResponseStatus, ReturnRequest, ReturnStatus, Tx, TxAction},
+    state::{
+        append_tx, get_order_participant, get_return_requests, get_seller_listing_refs,
+        load_config, may_load_order, may_load_seller_profile, order_key, save_return_request,
+        save_seller_profile, update_return_request, Order,
+    },
+    update_logic::{transfer_funds, transfer_uscrt, DENOM},
+};
+use cosmwasm_std::{
+    to_binary, Api, CanonicalAddr, Env, Extern, HandleResponse, HumanAddr, Querier, StdError,
+    StdResult, Storage, Uint128,
+};
+
+pub fn deposit_seller_bond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+) -> StdResult<HandleResponse> {
+    let seller_canonical_address = deps.api.canonical_address(&env.message.sender)?;
+    let mut profile = match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => profile,
+        None => {
+            return Err(StdError::generic_err(
+                "Only registered sellers can deposit a bond",
+            ))
+        }
+    };
+
+    let mut deposit: u128 = 0;
+    for coin in env.message.sent_funds.iter() {
+        if coin.denom != DENOM {
+            return Err(StdError::generic_err(format!(
+                "The bond can only be deposited in {}",
+                DENOM
+            )));
+        }
+        deposit += coin.amount.u128();
+    }
+    if deposit == 0 {
+        return Err(StdError::generic_err("No funds were sent to deposit"));
+    }
+
+    profile.bond = Uint128(profile.bond.u128() + deposit);
+    save_seller_profile(&mut deps.storage, &seller_canonical_address, &profile)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::DepositSellerBond {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+pub fn withdraw_seller_bond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    amount: Uint128,
+) -> StdResult<HandleResponse> {
+    let seller_canonical_address = deps.api.canonical_address(&env.message.sender)?;
+    let mut profile = match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => profile,
+        None => {
+            return Err(StdError::generic_err(
+                "Only registered sellers can withdraw a bond",
+            ))
+        }
+    };
+
+    let locked_bond = get_locked_bond(deps, &env, &seller_canonical_address)?;
+    let available_bond = profile.bond.u128().saturating_sub(locked_bond);
+    if amount.u128() == 0 || amount.u128() > available_bond {
+        return Err(StdError::generic_err(format!(
+            "Between 1 and {} {} of the bond can be withdrawn",
+            available_bond, DENOM
+        )));
+    }
+
+    profile.bond = Uint128(profile.bond.u128() - amount.u128());
+    save_seller_profile(&mut deps.storage, &seller_canonical_address, &profile)?;
+
+    let mut response = transfer_uscrt(&env.contract.address, &env.message.sender, amount.u128())?;
+    response.data = Some(to_binary(&HandleAnswer::WithdrawSellerBond {
+        status: ResponseStatus::Success,
+    })?);
+    Ok(response)
+}
+
+/// The part of a seller's bond (in uscrt) that returns may still need: all the units not refunded
+/// yet of the orders whose return window isn't over, and the units of the returns still waiting
+/// for a decision
+fn get_locked_bond<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    seller_canonical_address: &CanonicalAddr,
+) -> StdResult<u128> {
+    let config = load_config(&deps.storage)?;
+    let (listings, _len) =
+        get_seller_listing_refs(&deps.storage, seller_canonical_address, 0, u32::MAX)?;
+
+    let mut locked_bond: u128 = 0;
+    for listing in listings {
+        let order_key = order_key(&listing.category, &listing.url);
+        let order = match may_load_order(&deps.storage, &order_key)? {
+            Some(order) => order,
+            None => continue,
+        };
+        let return_requests = get_return_requests(&deps.storage, &order_key)?;
+        // The return window of an order that wasn't shipped yet is still to come
+        let return_window_open = match order.shipped_at {
+            Some(shipped_at) => env.block.time <= shipped_at.saturating_add(config.return_window),
+            None => true,
+        };
+
+        let locked_units = if return_window_open {
+            let refunded_units: u32 = return_requests
+                .iter()
+                .filter(|return_request| return_request.status == ReturnStatus::Approved)
+                .map(|return_request| return_request.quantity)
+                .sum();
+            order.total_quantity.saturating_sub(refunded_units)
+        } else {
+            return_requests
+                .iter()
+                .filter(|return_request| {
+                    return_request.status == ReturnStatus::Requested
+                        || return_request.status == ReturnStatus::Disputed
+                })
+                .map(|return_request| return_request.quantity)
+                .sum()
+        };
+        // The bond is kept in uscrt while prices are in scrt
+        locked_bond += locked_units as u128 * order.static_data.wanted_price.u128() * 1000000;
+    }
+    Ok(locked_bond)
+}
+
+pub fn request_return<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+    quantity: u32,
+    reason: String,
+) -> StdResult<HandleResponse> {
+    let order_key = order_key(&category, &url);
+    let order = load_order(deps, &order_key)?;
+
+    let shipped_at = match order.shipped_at {
+        Some(shipped_at) => shipped_at,
+        None => return Err(StdError::generic_err("The order wasn't shipped yet")),
+    };
+    let config = load_config(&deps.storage)?;
+    if env.block.time > shipped_at.saturating_add(config.return_window) {
+        return Err(StdError::generic_err(
+            "The return window of this order is over",
+        ));
+    }
+
+    let participant = match get_order_participant(&deps.storage, &order_key, &env.message.sender)? {
+        Some(participant) => participant,
+        None => {
+            return Err(StdError::generic_err(
+                "Only participants of the order can ask for a return",
+            ))
+        }
+    };
+    if quantity == 0 || quantity > participant.quantity {
+        return Err(StdError::generic_err(format!(
+            "The quantity to return must be between 1 and {}",
+            participant.quantity
+        )));
+    }
+    if get_user_return_request(deps, &order_key, &env.message.sender)?.is_some() {
+        return Err(StdError::generic_err(
+            "A return was already requested for this order",
+        ));
+    }
+
+    let return_request = ReturnRequest {
+        account_address: env.message.sender,
+        quantity,
+        reason,
+        requested_at: env.block.time,
+        status: ReturnStatus::Requested,
+    };
+    save_return_request(&mut deps.storage, &order_key, &return_request)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RequestReturn {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+pub fn approve_return<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+    buyer: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let order_key = order_key(&category, &url);
+    let order = load_order(deps, &order_key)?;
+    if order.static_data.seller_address != env.message.sender.to_string() {
+        return Err(StdError::unauthorized());
+    }
+
+    let return_request = load_return_request(deps, &order_key, &buyer)?;
+    if return_request.status != ReturnStatus::Requested {
+        return Err(StdError::generic_err(
+            "This return request was already handled",
+        ));
+    }
+
+    let mut response = refund_from_bond(deps, &env, &order_key, &order, return_request)?;
+    response.data = Some(to_binary(&HandleAnswer::ApproveReturn {
+        status: ResponseStatus::Success,
+    })?);
+    Ok(response)
+}
+
+pub fn reject_return<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+    buyer: HumanAddr,
+) -> StdResult<HandleResponse> {
+    let order_key = order_key(&category, &url);
+    let order = load_order(deps, &order_key)?;
+    if order.static_data.seller_address != env.message.sender.to_string() {
+        return Err(StdError::unauthorized());
+    }
+
+    let mut return_request = load_return_request(deps, &order_key, &buyer)?;
+    if return_request.status != ReturnStatus::Requested {
+        return Err(StdError::generic_err(
+            "This return request was already handled",
+        ));
+    }
+
+    // A rejected return is escalated to the admin
+    return_request.status = ReturnStatus::Disputed;
+    update_return_request(&mut deps.storage, &order_key, &return_request)?;
+
+    Ok(HandleResponse {
+        messages: vec![],
+        log: vec![],
+        data: Some(to_binary(&HandleAnswer::RejectReturn {
+            status: ResponseStatus::Success,
+        })?),
+    })
+}
+
+pub fn resolve_return_dispute<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+    buyer: HumanAddr,
+    refund: bool,
+) -> StdResult<HandleResponse> {
+    check_admin(deps, &env)?;
+
+    let order_key = order_key(&category, &url);
+    let order = load_order(deps, &order_key)?;
+    let mut return_request = load_return_request(deps, &order_key, &buyer)?;
+    if return_request.status != ReturnStatus::Disputed {
+        return Err(StdError::generic_err("This return request isn't disputed"));
+    }
+
+    let mut response = if refund {
+        refund_from_bond(deps, &env, &order_key, &order, return_request)?
+    } else {
+        return_request.status = ReturnStatus::Rejected;
+        update_return_request(&mut deps.storage, &order_key, &return_request)?;
+        HandleResponse::default()
+    };
+    response.data = Some(to_binary(&HandleAnswer::ResolveReturnDispute {
+        status: ResponseStatus::Success,
+    })?);
+    Ok(response)
+}
+
+/// Refunds the buyer for the returned units from the seller's bond
+fn refund_from_bond<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    order_key: &[u8],
+    order: &Order,
+    mut return_request: ReturnRequest,
+) -> StdResult<HandleResponse> {
+    let seller_canonical_address = deps
+        .api
+        .canonical_address(&HumanAddr(order.static_data.seller_address.clone()))?;
+    let mut profile = match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => profile,
+        None => {
+            return Err(StdError::generic_err(
+                "The seller of this order is not registered",
+            ))
+        }
+    };
+
+    let refund_amount = return_request.quantity as u128 * order.static_data.wanted_price.u128();
+    // The bond is kept in uscrt while prices are in scrt
+    let bond_left = match profile.bond.u128().checked_sub(refund_amount * 1000000) {
+        Some(bond_left) => bond_left,
+        None => {
+            return Err(StdError::generic_err(
+                "The seller's bond is too low to refund this return",
+            ))
+        }
+    };
+    profile.bond = Uint128(bond_left);
+    save_seller_profile(&mut deps.storage, &seller_canonical_address, &profile)?;
+
+    return_request.status = ReturnStatus::Approved;
+    update_return_request(&mut deps.storage, order_key, &return_request)?;
+
+    let buyer_canonical_address = deps
+        .api
+        .canonical_address(&return_request.account_address)?;
+    let tx = Tx {
+        // Assigned when stored
+        id: 0,
+        action: TxAction::ReturnRefund,
+        category: order.static_data.category.clone(),
+        url: order.static_data.url.clone(),
+        quantity: return_request.quantity,
+        amount: Uint128(refund_amount),
+        block_time: env.block.time,
+    };
+    append_tx(&mut deps.storage, &buyer_canonical_address, tx)?;
+
+    // Refunds go where the participant asked them to when joining
+    let refund_address =
+        get_order_participant(&deps.storage, order_key, &return_request.account_address)?
+            .and_then(|participant| participant.refund_address)
+            .unwrap_or(return_request.account_address);
+    transfer_funds(&env.contract.address, &refund_address, refund_amount)
+}
+
+fn load_order<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    order_key: &[u8],
+) -> StdResult<Order> {
+    match may_load_order(&deps.storage, order_key)? {
+        Some(order) => Ok(order),
+        None => Err(StdError::generic_err("No such order")),
+    }
+}
+
+fn get_user_return_request<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    order_key: &[u8],
+    buyer: &HumanAddr,
+) -> StdResult<Option<ReturnRequest>> {
+    let return_requests = get_return_requests(&deps.storage, order_key)?;
+    Ok(return_requests
+        .into_iter()
+        .find(|return_request| return_request.account_address == *buyer))
+}
+
+fn load_return_request<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    order_key: &[u8],
+    buyer: &HumanAddr,
+) -> StdResult<ReturnRequest> {
+    match get_user_return_request(deps, order_key, buyer)? {
+        Some(return_request) => Ok(return_request),
+        None => Err(StdError::generic_err("Return request wasn't found")),
+    }
+}
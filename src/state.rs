@@ -1,54 +1,490 @@
-use secret_toolkit::storage::{AppendStore, AppendStoreMut};
+use secret_toolkit::serialization::Bincode2;
+use secret_toolkit::storage::{AppendStore, AppendStoreMut, TypedStore, TypedStoreMut};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::msg::{StaticItemData, UpdateItemData, UserItemDetails, UserProductQuantity};
+use crate::msg::{
+    Category, ContractStatus, MarketStats, OrderParticipant, ReturnRequest, SellerProfile,
+    StaticItemData, Tx, UpdateItemData, UserContactData, UserItemDetails, UserProductQuantity,
+};
 
-use cosmwasm_std::{HumanAddr, ReadonlyStorage, StdError, StdResult, Storage};
+use cosmwasm_std::{CanonicalAddr, HumanAddr, ReadonlyStorage, StdError, StdResult, Storage};
 use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
-const PREFIX_LAPTOPS_STATIC: &[u8] = b"laptops-static";
-const PREFIX_LAPTOPS_DYNAMIC: &[u8] = b"laptops-dynamic";
-const PREFIX_LAPTOPS_DYNAMIC_USERS: &[u8] = b"laptops-dynamic-users";
-
-const PREFIX_KEYBOARDS_STATIC: &[u8] = b"keyboards-static";
-const PREFIX_KEYBOARDS_DYNAMIC: &[u8] = b"keyboards-dynamic";
-const PREFIX_KEYBOARDS_DYNAMIC_USERS: &[u8] = b"keyboards-dynamic-users";
-
-const PREFIX_MOUSES_STATIC: &[u8] = b"mouses-static";
-const PREFIX_MOUSES_DYNAMIC: &[u8] = b"mouses-dynamic";
-const PREFIX_MOUSES_DYNAMIC_USERS: &[u8] = b"mouses-dynamic-users";
-
-const PREFIX_MOUSE_PADS_STATIC: &[u8] = b"mouse-pads-static";
-const PREFIX_MOUSE_PADS_DYNAMIC: &[u8] = b"mouse-pads-dynamic";
-const PREFIX_MOUSE_PADS_DYNAMIC_USERS: &[u8] = b"mouse-pads-dynamic-users";
-
-const LAPTOPS: &[u8] = b"laptops";
-const KEYBOARDS: &[u8] = b"keyboards";
-const MOUSES: &[u8] = b"mouses";
-const MOUSE_PADS: &[u8] = b"mouse-pads";
-
-pub fn get_category_prefixes(category: &[u8]) -> StdResult<(&[u8], &[u8], &[u8])> {
-    match category {
-        LAPTOPS => Ok((
-            PREFIX_LAPTOPS_STATIC,
-            PREFIX_LAPTOPS_DYNAMIC,
-            PREFIX_LAPTOPS_DYNAMIC_USERS,
-        )),
-        KEYBOARDS => Ok((
-            PREFIX_KEYBOARDS_STATIC,
-            PREFIX_KEYBOARDS_DYNAMIC,
-            PREFIX_KEYBOARDS_DYNAMIC_USERS,
-        )),
-        MOUSES => Ok((
-            PREFIX_MOUSES_STATIC,
-            PREFIX_MOUSES_DYNAMIC,
-            PREFIX_MOUSES_DYNAMIC_USERS,
-        )),
-        MOUSE_PADS => Ok((
-            PREFIX_MOUSE_PADS_STATIC,
-            PREFIX_MOUSE_PADS_DYNAMIC,
-            PREFIX_MOUSE_PADS_DYNAMIC_USERS,
-        )),
-        _ => Err(StdError::generic_err("No such category!")),
+use secret_toolkit::crypto::sha_256;
+
+const KEY_CONFIG: &[u8] = b"config";
+const KEY_BLOCK_TIME: &[u8] = b"block-time";
+pub const PREFIX_REVOKED_PERMITS: &str = "revoked-permits";
+const PREFIX_SELLERS: &[u8] = b"sellers";
+const PREFIX_CONTACT_PROFILES: &[u8] = b"contact-profiles";
+const PREFIX_ORDERS: &[u8] = b"orders";
+const PREFIX_ORDER_PARTICIPANTS: &[u8] = b"order-participants";
+const PREFIX_USER_ORDERS: &[u8] = b"user-orders";
+const PREFIX_SELLER_LISTINGS: &[u8] = b"seller-listings";
+const PREFIX_STATS: &[u8] = b"stats";
+const PREFIX_TRANSACTIONS: &[u8] = b"transactions";
+const KEY_GLOBAL_STATS: &[u8] = b"global";
+const PREFIX_RETURN_REQUESTS: &[u8] = b"return-requests";
+const KEY_CATEGORIES: &[u8] = b"categories";
+/// Where AppendStoreMut keeps its length. Only `rewrite_user_records_len` relies on it, and
+/// `test_user_records_layout` checks it against AppendStoreMut
+const APPEND_STORE_LEN_KEY: &[u8] = b"len";
+
+pub const MAX_FEE_BPS: u16 = 10000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Config {
+    pub admin: HumanAddr,
+    /// The address the admin proposed to hand the role to, until it accepts
+    pub pending_admin: Option<HumanAddr>,
+    pub status: ContractStatus,
+    pub return_window: u64,
+    /// The part of each group payment kept as a fee, in basis points
+    pub fee_bps: u16,
+    pub fee_recipient: HumanAddr,
+    /// The largest page the paginated queries return
+    pub max_page_size: u32,
+    /// Needed to validate query permits, as queries don't get the env
+    pub contract_address: HumanAddr,
+    /// The hash of the seed given at init, used to generate viewing keys
+    pub prng_seed: Vec<u8>,
+}
+
+pub fn save_config<S: Storage>(storage: &mut S, config: &Config) -> StdResult<()> {
+    TypedStoreMut::attach(storage).store(KEY_CONFIG, config)
+}
+
+pub fn load_config<S: ReadonlyStorage>(storage: &S) -> StdResult<Config> {
+    TypedStore::attach(storage).load(KEY_CONFIG)
+}
+
+/// Queries don't get the env, so they use the block time of the latest transaction instead
+pub fn save_block_time<S: Storage>(storage: &mut S, block_time: u64) -> StdResult<()> {
+    TypedStoreMut::attach(storage).store(KEY_BLOCK_TIME, &block_time)
+}
+
+pub fn load_block_time<S: ReadonlyStorage>(storage: &S) -> StdResult<u64> {
+    TypedStore::attach(storage).load(KEY_BLOCK_TIME)
+}
+
+// [TRANSACTIONS, userAddress] ==> Vec<Tx>
+/// The id of the transaction is its position in the user's history
+pub fn append_tx<S: Storage>(storage: &mut S, user: &CanonicalAddr, mut tx: Tx) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_TRANSACTIONS, user.as_slice()], storage);
+    let mut storage = AppendStoreMut::<Tx, _, _>::attach_or_create(&mut storage)?;
+    tx.id = storage.len() as u64;
+    storage.push(&tx)
+}
+
+/// Returns the page of the user's transactions, the latest first, and their total number
+pub fn get_txs<S: ReadonlyStorage>(
+    storage: &S,
+    user: &CanonicalAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<(Vec<Tx>, u32)> {
+    let store =
+        ReadonlyPrefixedStorage::multilevel(&[PREFIX_TRANSACTIONS, user.as_slice()], storage);
+
+    let store = AppendStore::<Tx, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok((vec![], 0));
+    };
+    let txs: StdResult<Vec<Tx>> = store
+        .iter()
+        .rev()
+        .skip(get_page_start(page, page_size)?)
+        .take(page_size as usize)
+        .collect();
+    txs.map(|txs| (txs, store.len()))
+}
+
+// [STATS, category] ==> stats of the category, [STATS, GLOBAL] ==> stats of the marketplace
+pub fn update_stats<S: Storage, F: Fn(&mut MarketStats)>(
+    storage: &mut S,
+    category: &str,
+    update: F,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::new(PREFIX_STATS, storage);
+    // Category names can't collide with the global key, as they're prefixed
+    let category_key = [b"category-", category.as_bytes()].concat();
+    for key in [category_key.as_slice(), KEY_GLOBAL_STATS] {
+        let mut stats: MarketStats = TypedStore::attach(&storage)
+            .may_load(key)?
+            .unwrap_or_default();
+        update(&mut stats);
+        TypedStoreMut::attach(&mut storage).store(key, &stats)?;
+    }
+    Ok(())
+}
+
+pub fn load_stats<S: ReadonlyStorage>(
+    storage: &S,
+    category: Option<&str>,
+) -> StdResult<MarketStats> {
+    let storage = ReadonlyPrefixedStorage::new(PREFIX_STATS, storage);
+    let key = match category {
+        Some(category) => [b"category-", category.as_bytes()].concat(),
+        None => KEY_GLOBAL_STATS.to_vec(),
+    };
+    Ok(TypedStore::attach(&storage)
+        .may_load(&key)?
+        .unwrap_or_default())
+}
+
+// [SELLERS, sellerAddress] ==> seller profile
+pub fn save_seller_profile<S: Storage>(
+    storage: &mut S,
+    seller: &CanonicalAddr,
+    profile: &SellerProfile,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::new(PREFIX_SELLERS, storage);
+    TypedStoreMut::attach(&mut storage).store(seller.as_slice(), profile)
+}
+
+pub fn may_load_seller_profile<S: ReadonlyStorage>(
+    storage: &S,
+    seller: &CanonicalAddr,
+) -> StdResult<Option<SellerProfile>> {
+    let storage = ReadonlyPrefixedStorage::new(PREFIX_SELLERS, storage);
+    TypedStore::attach(&storage).may_load(seller.as_slice())
+}
+
+// [CONTACT_PROFILES, userAddress] ==> contact profile
+pub fn save_contact_profile<S: Storage>(
+    storage: &mut S,
+    user: &CanonicalAddr,
+    profile: &UserContactData,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::new(PREFIX_CONTACT_PROFILES, storage);
+    TypedStoreMut::attach(&mut storage).store(user.as_slice(), profile)
+}
+
+pub fn may_load_contact_profile<S: ReadonlyStorage>(
+    storage: &S,
+    user: &CanonicalAddr,
+) -> StdResult<Option<UserContactData>> {
+    let storage = ReadonlyPrefixedStorage::new(PREFIX_CONTACT_PROFILES, storage);
+    TypedStore::attach(&storage).may_load(user.as_slice())
+}
+
+/// The readable contact data of a participation: its own override, or else the user's profile.
+/// There is none if the user sealed their contact data to the seller instead
+pub fn get_participation_contact_data<S: ReadonlyStorage>(
+    storage: &S,
+    user: &CanonicalAddr,
+    user_details: &UserItemDetails,
+) -> StdResult<Option<UserContactData>> {
+    if user_details.sealed_contact_data.is_some() {
+        return Ok(None);
+    }
+    match &user_details.contact_data {
+        Some(contact_data) => Ok(Some(contact_data.clone())),
+        None => may_load_contact_profile(storage, user),
+    }
+}
+
+/// An item whose group size goal was reached, kept until it is shipped
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Order {
+    pub static_data: StaticItemData,
+    pub total_quantity: u32,
+    pub shipped_at: Option<u64>,
+}
+
+pub fn order_key(category: &str, url: &str) -> [u8; 32] {
+    sha_256(base64::encode(format!("{}/{}", category, url)).as_bytes())
+}
+
+// [ORDERS, orderKey] ==> order
+pub fn save_order<S: Storage>(storage: &mut S, key: &[u8], order: &Order) -> StdResult<()> {
+    let mut storage = PrefixedStorage::new(PREFIX_ORDERS, storage);
+    TypedStoreMut::attach(&mut storage).store(key, order)
+}
+
+pub fn may_load_order<S: ReadonlyStorage>(storage: &S, key: &[u8]) -> StdResult<Option<Order>> {
+    let storage = ReadonlyPrefixedStorage::new(PREFIX_ORDERS, storage);
+    TypedStore::attach(&storage).may_load(key)
+}
+
+// [ORDER_PARTICIPANTS, orderKey] ==> Vec<OrderParticipant>
+pub fn save_order_participant<S: Storage>(
+    storage: &mut S,
+    key: &[u8],
+    value: &OrderParticipant,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_ORDER_PARTICIPANTS, key], storage);
+    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
+    storage.push(value)
+}
+
+/// Points to an item in its category. Stays valid once the item becomes an order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ListingRef {
+    pub category: String,
+    pub url: String,
+}
+
+// [SELLER_LISTINGS, sellerAddress] ==> Vec<ListingRef>
+pub fn save_seller_listing<S: Storage>(
+    storage: &mut S,
+    seller: &CanonicalAddr,
+    listing: &ListingRef,
+) -> StdResult<()> {
+    let mut storage =
+        PrefixedStorage::multilevel(&[PREFIX_SELLER_LISTINGS, seller.as_slice()], storage);
+    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
+    storage.push(listing)
+}
+
+pub fn get_seller_listing_refs<S: ReadonlyStorage>(
+    storage: &S,
+    seller: &CanonicalAddr,
+    page: u32,
+    page_size: u32,
+) -> StdResult<(Vec<ListingRef>, u32)> {
+    let store =
+        ReadonlyPrefixedStorage::multilevel(&[PREFIX_SELLER_LISTINGS, seller.as_slice()], storage);
+
+    let store = AppendStore::<ListingRef, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok((vec![], 0));
+    };
+
+    let listings: StdResult<Vec<ListingRef>> = store
+        .iter()
+        .skip(get_page_start(page, page_size)?)
+        .take(page_size as _)
+        .collect();
+    listings.map(|listings| (listings, store.len()))
+}
+
+pub fn remove_seller_listing<S: Storage>(
+    storage: &mut S,
+    seller: &CanonicalAddr,
+    listing: &ListingRef,
+) -> StdResult<()> {
+    let mut storage =
+        PrefixedStorage::multilevel(&[PREFIX_SELLER_LISTINGS, seller.as_slice()], storage);
+    let mut storage = AppendStoreMut::<ListingRef, _, _>::attach_or_create(&mut storage)?;
+
+    let len = storage.len();
+    for i in 0..len {
+        if storage.get_at(i)? == *listing {
+            let last = storage.pop()?;
+            if i != len - 1 {
+                storage.set_at(i, &last)?;
+            }
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// [USER_ORDERS, userAddress] ==> Vec<orderKey>
+pub fn save_user_order<S: Storage>(
+    storage: &mut S,
+    user: &CanonicalAddr,
+    order_key: &[u8],
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_USER_ORDERS, user.as_slice()], storage);
+    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
+    storage.push(&order_key.to_vec())
+}
+
+pub fn get_user_orders<S: ReadonlyStorage>(
+    storage: &S,
+    user: &CanonicalAddr,
+) -> StdResult<Vec<Vec<u8>>> {
+    let store =
+        ReadonlyPrefixedStorage::multilevel(&[PREFIX_USER_ORDERS, user.as_slice()], storage);
+
+    let store = AppendStore::<Vec<u8>, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+    store.iter().collect()
+}
+
+pub fn get_order_participants<S: ReadonlyStorage>(
+    storage: &S,
+    key: &[u8],
+    page: u32,
+    page_size: u32,
+) -> StdResult<(Vec<OrderParticipant>, u32)> {
+    let store = ReadonlyPrefixedStorage::multilevel(&[PREFIX_ORDER_PARTICIPANTS, key], storage);
+
+    // Try to access the storage of participants for the order.
+    // If it doesn't exist yet, return an empty list of participants.
+    let store = AppendStore::<OrderParticipant, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok((vec![], 0));
+    };
+
+    let participants: StdResult<Vec<OrderParticipant>> = store
+        .iter()
+        .skip(get_page_start(page, page_size)?)
+        .take(page_size as _)
+        .collect();
+    participants.map(|participants| (participants, store.len()))
+}
+
+pub fn get_order_participant<S: ReadonlyStorage>(
+    storage: &S,
+    key: &[u8],
+    user_address: &HumanAddr,
+) -> StdResult<Option<OrderParticipant>> {
+    let store = ReadonlyPrefixedStorage::multilevel(&[PREFIX_ORDER_PARTICIPANTS, key], storage);
+
+    // Try to access the storage of participants for the order.
+    // If it doesn't exist yet, the user isn't a participant.
+    let store = AppendStore::<OrderParticipant, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok(None);
+    };
+
+    for participant in store.iter() {
+        let unwrapped = participant?;
+        if unwrapped.account_address == *user_address {
+            return Ok(Some(unwrapped));
+        }
+    }
+    Ok(None)
+}
+
+/// Drops the contact data of all the participants of an order, keeping their quantities
+pub fn remove_order_participants_contact_data<S: Storage>(
+    storage: &mut S,
+    key: &[u8],
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_ORDER_PARTICIPANTS, key], storage);
+    let mut storage = AppendStoreMut::<OrderParticipant, _, _>::attach_or_create(&mut storage)?;
+
+    for i in 0..storage.len() {
+        let mut participant = storage.get_at(i)?;
+        participant.contact_data = None;
+        participant.sealed_contact_data = None;
+        storage.set_at(i, &participant)?;
+    }
+    Ok(())
+}
+
+// [RETURN_REQUESTS, orderKey] ==> Vec<ReturnRequest>
+pub fn save_return_request<S: Storage>(
+    storage: &mut S,
+    key: &[u8],
+    value: &ReturnRequest,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_RETURN_REQUESTS, key], storage);
+    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
+    storage.push(value)
+}
+
+pub fn get_return_requests<S: ReadonlyStorage>(
+    storage: &S,
+    key: &[u8],
+) -> StdResult<Vec<ReturnRequest>> {
+    let store = ReadonlyPrefixedStorage::multilevel(&[PREFIX_RETURN_REQUESTS, key], storage);
+
+    // Try to access the storage of return requests for the order.
+    // If it doesn't exist yet, return an empty list of return requests.
+    let store = AppendStore::<ReturnRequest, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+
+    let return_requests: StdResult<Vec<ReturnRequest>> = store.iter().collect();
+    return_requests
+}
+
+/// Overwrites the return request of a user, returning an error if they have none
+pub fn update_return_request<S: Storage>(
+    storage: &mut S,
+    key: &[u8],
+    value: &ReturnRequest,
+) -> StdResult<()> {
+    let mut storage = PrefixedStorage::multilevel(&[PREFIX_RETURN_REQUESTS, key], storage);
+    let mut storage = AppendStoreMut::<ReturnRequest, _, _>::attach_or_create(&mut storage)?;
+
+    for i in 0..storage.len() {
+        if storage.get_at(i)?.account_address == value.account_address {
+            return storage.set_at(i, value);
+        }
+    }
+    Err(StdError::generic_err("Return request wasn't found"))
+}
+
+// [CATEGORIES] ==> Vec<Category>
+pub fn save_categories<S: Storage>(storage: &mut S, categories: &[Category]) -> StdResult<()> {
+    TypedStoreMut::attach(storage).store(KEY_CATEGORIES, &categories.to_vec())
+}
+
+pub fn load_categories<S: ReadonlyStorage>(storage: &S) -> StdResult<Vec<Category>> {
+    Ok(TypedStore::attach(storage)
+        .may_load(KEY_CATEGORIES)?
+        .unwrap_or_default())
+}
+
+pub fn may_load_category<S: ReadonlyStorage>(
+    storage: &S,
+    key: &str,
+) -> StdResult<Option<Category>> {
+    Ok(load_categories(storage)?
+        .into_iter()
+        .find(|category| category.key == key))
+}
+
+/// The keys of all the categories, including the disabled ones
+pub fn get_category_keys<S: ReadonlyStorage>(storage: &S) -> StdResult<Vec<String>> {
+    Ok(load_categories(storage)?
+        .into_iter()
+        .map(|category| category.key)
+        .collect())
+}
+
+/// The key of the category followed by the keys of all its subcategories, at any depth
+pub fn get_category_tree_keys<S: ReadonlyStorage>(
+    storage: &S,
+    category: &str,
+) -> StdResult<Vec<String>> {
+    let categories = load_categories(storage)?;
+    let mut keys = vec![category.to_string()];
+    let mut next = 0;
+    while next < keys.len() {
+        for subcategory in categories.iter() {
+            if subcategory.parent.as_deref() == Some(keys[next].as_str()) {
+                keys.push(subcategory.key.clone());
+            }
+        }
+        next += 1;
     }
+    Ok(keys)
+}
+
+/// The storage prefixes of the category's items are derived from its key, which never changes
+pub fn get_category_prefixes<S: ReadonlyStorage>(
+    storage: &S,
+    category: &str,
+) -> StdResult<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    if may_load_category(storage, category)?.is_none() {
+        return Err(StdError::generic_err("No such category!"));
+    }
+    Ok((
+        format!("{}-static", category).into_bytes(),
+        format!("{}-dynamic", category).into_bytes(),
+        format!("{}-dynamic-users", category).into_bytes(),
+    ))
 }
 
 // [CATEGORY_STATIC] ==> static item data
@@ -81,6 +517,39 @@ pub fn get_category_items<S: ReadonlyStorage>(
     // Ok(items_static_data?)
 }
 
+pub fn get_category_items_page<S: ReadonlyStorage>(
+    storage: &S,
+    prefix_static: &[u8],
+    page: u32,
+    page_size: u32,
+) -> StdResult<(Vec<StaticItemData>, u32)> {
+    let store = ReadonlyPrefixedStorage::multilevel(&[prefix_static], storage);
+
+    // Try to access the storage of items for the category.
+    // If it doesn't exist yet, return an empty list of items.
+    let store = AppendStore::<StaticItemData, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok((vec![], 0));
+    };
+
+    let items_static_data: StdResult<Vec<StaticItemData>> = store
+        .iter()
+        .skip(get_page_start(page, page_size)?)
+        .take(page_size as _)
+        .collect();
+    items_static_data.map(|items_static_data| (items_static_data, store.len()))
+}
+
+/// The index of the first element of the page. Fails for pages beyond any store
+pub fn get_page_start(page: u32, page_size: u32) -> StdResult<usize> {
+    match page.checked_mul(page_size) {
+        Some(page_start) => Ok(page_start as usize),
+        None => Err(StdError::generic_err("The page is out of range")),
+    }
+}
+
 // remove_category_items(&mut deps.storage, &static_prefix, &update_item_data.url)?;
 pub fn remove_category_item<S: Storage>(
     storage: &mut S,
@@ -201,6 +670,56 @@ pub fn remove_current_group_size<S: Storage>(
     Ok(())
 }
 
+/// Reads all the records of an append store of a user's participation, so that the reads don't
+/// depend on where the wanted record is
+fn load_user_records<T: Serialize + DeserializeOwned, S: Storage>(
+    store: &AppendStoreMut<T, S, Bincode2>,
+) -> StdResult<Vec<T>> {
+    store.iter().collect()
+}
+
+/// Writes the length of an append store unchanged, as AppendStoreMut only writes it on a push or
+/// a pop
+fn rewrite_user_records_len<T: Serialize + DeserializeOwned, S: Storage>(
+    store: &mut AppendStoreMut<T, S, Bincode2>,
+) {
+    let len = store.len();
+    store
+        .storage()
+        .set(APPEND_STORE_LEN_KEY, &len.to_be_bytes());
+}
+
+/// Every write to the records of a participation - a join, an update, a leave or a decoy - is a
+/// single record and the length of the append store, so they all look alike
+fn set_user_record<T: Serialize + DeserializeOwned, S: Storage>(
+    store: &mut AppendStoreMut<T, S, Bincode2>,
+    pos: u32,
+    value: &T,
+) -> StdResult<()> {
+    store.set_at(pos, value)?;
+    rewrite_user_records_len(store);
+    Ok(())
+}
+
+/// Writes a record of a participation unchanged: the one `matches` finds, or else the last one.
+/// A store without records only gets its length written
+fn rewrite_user_record<T: Serialize + DeserializeOwned, S: Storage>(
+    storage: &mut S,
+    matches: impl Fn(&T) -> bool,
+) -> StdResult<()> {
+    let mut store = AppendStoreMut::<T, _, _>::attach_or_create(storage)?;
+    let records = load_user_records(&store)?;
+    let pos = match records.iter().position(matches) {
+        Some(c) => c,
+        None if records.is_empty() => {
+            rewrite_user_records_len(&mut store);
+            return Ok(());
+        }
+        None => records.len() - 1,
+    };
+    set_user_record(&mut store, pos as u32, &records[pos])
+}
+
 pub fn save_category_element_user<S: Storage>(
     storage: &mut S,
     key: &[u8],
@@ -208,8 +727,9 @@ pub fn save_category_element_user<S: Storage>(
     value: &UserProductQuantity,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic, key], storage);
-    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
-    storage.push(value)
+    let mut store = AppendStoreMut::attach_or_create(&mut storage)?;
+    load_user_records(&store)?;
+    store.push(value)
 }
 
 pub fn save_category_element_user_item_details<S: Storage>(
@@ -219,8 +739,9 @@ pub fn save_category_element_user_item_details<S: Storage>(
     value: &UserItemDetails,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic_users, key], storage);
-    let mut storage = AppendStoreMut::attach_or_create(&mut storage)?;
-    storage.push(value)
+    let mut store = AppendStoreMut::attach_or_create(&mut storage)?;
+    load_user_records(&store)?;
+    store.push(value)
 }
 
 // remove_user_item_quantity(&mut deps.storage, &prefix_dynamic, &key, &update_item_data.url)?;
@@ -231,35 +752,21 @@ pub fn remove_user_item_quantity<S: Storage>(
     url: &str,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic, key], storage);
-    let mut storage = AppendStoreMut::<UserProductQuantity, _, _>::attach_or_create(&mut storage)?;
-
-    match storage.len() {
-        0 => Ok(()),
-        len => {
-            let mut c: u32 = 0;
-            for user_item_quantity in storage.iter() {
-                let unwrapped = user_item_quantity?;
-                if unwrapped.url == url {
-                    break;
-                }
-                c += 1;
-            }
-            if c == len {
-                return Err(StdError::generic_err("Item to remove wasn't found"));
-            };
-
-            if c == len - 1 {
-                storage.pop()?;
-                return Ok(());
-            }
-
-            let last = storage.get_at(len - 1)?;
-            storage.set_at(c, &last)?;
-            storage.pop()?;
+    let mut store = AppendStoreMut::<UserProductQuantity, _, _>::attach_or_create(&mut storage)?;
+    let records = load_user_records(&store)?;
+    let last = match records.last() {
+        Some(last) => last,
+        None => return Ok(()),
+    };
+    let c = match records.iter().position(|record| record.url == url) {
+        Some(c) => c as u32,
+        None => return Err(StdError::generic_err("Item to remove wasn't found")),
+    };
 
-            Ok(())
-        }
-    }
+    // The last record takes the place of the removed one
+    store.set_at(c, last)?;
+    store.pop()?;
+    Ok(())
 }
 
 pub fn update_user_item_quantity<S: Storage>(
@@ -269,39 +776,24 @@ pub fn update_user_item_quantity<S: Storage>(
     update_item_data: &UpdateItemData,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic, key], storage);
-    let mut storage = AppendStoreMut::<UserProductQuantity, _, _>::attach_or_create(&mut storage)?;
-
-    match storage.len() {
-        0 => Ok(()),
-        len => {
-            let mut c: u32 = 0;
-            for user_item_quantity in storage.iter() {
-                let unwrapped = user_item_quantity?;
-                if unwrapped.url == update_item_data.url {
-                    break;
-                }
-                c += 1;
-            }
-            if c == len {
-                return Err(StdError::generic_err("Item to update wasn't found"));
-            };
-
-            // if c == len - 1 {
-            //     storage.pop()?;
-            //     return Ok(());
-            // }
-
-            // let last = storage.get_at(len - 1)?;
-            let updated_user_item_quantity = UserProductQuantity {
-                quantity: update_item_data.user_details.quantity,
-                url: update_item_data.url.clone(),
-            };
-            storage.set_at(c, &updated_user_item_quantity)?;
-            // storage.pop()?;
-
-            Ok(())
-        }
+    let mut store = AppendStoreMut::<UserProductQuantity, _, _>::attach_or_create(&mut storage)?;
+    let records = load_user_records(&store)?;
+    if records.is_empty() {
+        return Ok(());
     }
+    let c = match records
+        .iter()
+        .position(|record| record.url == update_item_data.url)
+    {
+        Some(c) => c as u32,
+        None => return Err(StdError::generic_err("Item to update wasn't found")),
+    };
+
+    let updated_user_item_quantity = UserProductQuantity {
+        quantity: update_item_data.user_details.quantity,
+        url: update_item_data.url.clone(),
+    };
+    set_user_record(&mut store, c, &updated_user_item_quantity)
 }
 
 // [CATEGORY_USERS_DATA, userAddress] ==> Vec<UserProductQuantity>
@@ -354,6 +846,31 @@ pub fn get_category_user_items_quantities_by_url<S: ReadonlyStorage>(
     Ok(None)
 }
 
+/// Reads and rewrites a user's records of an item unchanged, so that the storage access of a
+/// decoy looks like the one of a real update. A user without a record of the item has the last
+/// record of the store rewritten instead
+pub fn rewrite_category_item_user_records<S: Storage>(
+    storage: &mut S,
+    prefix_dynamic: &[u8],
+    prefix_dynamic_users: &[u8],
+    key: &[u8],
+    user_key: &[u8],
+    user_address: &HumanAddr,
+    url: &str,
+) -> StdResult<()> {
+    {
+        let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic, user_key], storage);
+        rewrite_user_record(&mut storage, |record: &UserProductQuantity| {
+            record.url == url
+        })?;
+    }
+
+    let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic_users, key], storage);
+    rewrite_user_record(&mut storage, |record: &UserItemDetails| {
+        record.account_address == *user_address
+    })
+}
+
 pub fn get_category_item_user_details<S: ReadonlyStorage>(
     storage: &S,
     prefix_dynamic_users: &[u8],
@@ -380,6 +897,26 @@ pub fn get_category_item_user_details<S: ReadonlyStorage>(
     Ok(None)
 }
 
+pub fn get_all_category_item_users_details<S: ReadonlyStorage>(
+    storage: &S,
+    prefix_dynamic_users: &[u8],
+    key: &[u8],
+) -> StdResult<Vec<UserItemDetails>> {
+    let store = ReadonlyPrefixedStorage::multilevel(&[prefix_dynamic_users, key], storage);
+
+    // Try to access the storage of transfers for the account.
+    // If it doesn't exist yet, return an empty list of transfers.
+    let store = AppendStore::<UserItemDetails, _, _>::attach(&store);
+    let store = if let Some(result) = store {
+        result?
+    } else {
+        return Ok(vec![]);
+    };
+
+    let users_details: StdResult<Vec<UserItemDetails>> = store.iter().collect();
+    users_details
+}
+
 pub fn get_all_participating_users_addresses<S: ReadonlyStorage>(
     storage: &S,
     prefix_dynamic_users: &[u8],
@@ -412,35 +949,24 @@ pub fn remove_category_item_user_details<S: Storage>(
     user_address: &HumanAddr,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic_users, key], storage);
-    let mut storage = AppendStoreMut::<UserItemDetails, _, _>::attach_or_create(&mut storage)?;
-
-    match storage.len() {
-        0 => Ok(()),
-        len => {
-            let mut c: u32 = 0;
-            for user_item_quantity in storage.iter() {
-                let unwrapped = user_item_quantity?;
-                if unwrapped.account_address == *user_address {
-                    break;
-                }
-                c += 1;
-            }
-            if c == len {
-                return Err(StdError::generic_err("Item to remove wasn't found"));
-            };
-
-            if c == len - 1 {
-                storage.pop()?;
-                return Ok(());
-            }
-
-            let last = storage.get_at(len - 1)?;
-            storage.set_at(c, &last)?;
-            storage.pop()?;
+    let mut store = AppendStoreMut::<UserItemDetails, _, _>::attach_or_create(&mut storage)?;
+    let records = load_user_records(&store)?;
+    let last = match records.last() {
+        Some(last) => last,
+        None => return Ok(()),
+    };
+    let c = match records
+        .iter()
+        .position(|record| record.account_address == *user_address)
+    {
+        Some(c) => c as u32,
+        None => return Err(StdError::generic_err("Item to remove wasn't found")),
+    };
 
-            Ok(())
-        }
-    }
+    // The last record takes the place of the removed one
+    store.set_at(c, last)?;
+    store.pop()?;
+    Ok(())
 }
 
 pub fn remove_all_category_item_users_details<S: Storage>(
@@ -466,36 +992,21 @@ pub fn update_category_item_user_details<S: Storage>(
     storage: &mut S,
     prefix_dynamic_users: &[u8],
     key: &[u8],
-    update_item_data: &UpdateItemData,
+    value: &UserItemDetails,
 ) -> StdResult<()> {
     let mut storage = PrefixedStorage::multilevel(&[prefix_dynamic_users, key], storage);
-    let mut storage = AppendStoreMut::<UserItemDetails, _, _>::attach_or_create(&mut storage)?;
-
-    match storage.len() {
-        0 => Ok(()),
-        len => {
-            let mut c: u32 = 0;
-            for user_item_quantity in storage.iter() {
-                let unwrapped = user_item_quantity?;
-                if unwrapped.account_address == update_item_data.user_details.account_address {
-                    break;
-                }
-                c += 1;
-            }
-            if c == len {
-                return Err(StdError::generic_err("Item to update wasn't found"));
-            };
-
-            // if c == len - 1 {
-            //     storage.pop()?;
-            //     return Ok(());
-            // }
-
-            // let last = storage.get_at(len - 1)?;
-            storage.set_at(c, &update_item_data.user_details)?;
-            // storage.pop()?;
-
-            Ok(())
-        }
+    let mut store = AppendStoreMut::<UserItemDetails, _, _>::attach_or_create(&mut storage)?;
+    let records = load_user_records(&store)?;
+    if records.is_empty() {
+        return Ok(());
     }
+    let c = match records
+        .iter()
+        .position(|record| record.account_address == value.account_address)
+    {
+        Some(c) => c as u32,
+        None => return Err(StdError::generic_err("Item to update wasn't found")),
+    };
+
+    set_user_record(&mut store, c, value)
 }
@@ -1,53 +1,134 @@
 use crate::{
     handle::remove_item_authenticated,
-    msg::{HandleAnswer, ResponseStatus, UpdateItemData, UserProductQuantity},
+    msg::{
+        ContractStatus, HandleAnswer, MarketStats, OrderParticipant, ResponseStatus, Tx, TxAction,
+        UpdateItemData, UserItemDetails, UserItemUpdate, UserProductQuantity,
+    },
     state::{
-        get_category_item_by_url, get_category_item_group_size, get_category_prefixes,
-        get_category_user_items_quantities_by_url, remove_category_item_user_details,
-        remove_user_item_quantity, save_category_element_user,
-        save_category_element_user_item_details, update_category_item_user_details,
-        update_current_group_size, update_user_item_quantity,
+        append_tx, get_all_category_item_users_details, get_category_item_by_url,
+        get_category_item_group_size, get_category_item_user_details, get_category_prefixes,
+        get_category_user_items_quantities_by_url, get_participation_contact_data, load_config,
+        may_load_seller_profile, order_key, remove_category_item_user_details,
+        remove_user_item_quantity, rewrite_category_item_user_records, save_category_element_user,
+        save_category_element_user_item_details, save_order, save_order_participant,
+        save_user_order, update_category_item_user_details, update_current_group_size,
+        update_stats, update_user_item_quantity, Order, MAX_FEE_BPS,
     },
 };
 use cosmwasm_std::{
     to_binary, Api, BankMsg, Coin, CosmosMsg, Env, Extern, HandleResponse, HumanAddr, Querier,
     StdError, StdResult, Storage, Uint128,
 };
-use secret_toolkit::crypto::sha_256;
+use secret_toolkit::crypto::{sha_256, Prng};
+
+/// Prices are in SCRT, while funds move in uscrt
+pub const DENOM: &str = "uscrt";
 
 pub fn update_user_for_item<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: Env,
     update_item_data: UpdateItemData,
+) -> StdResult<HandleResponse> {
+    let mut response = update_user_quantity(deps, env, update_item_data)?;
+
+    // Answer the same way whether the user joined, got refunded or completed the group
+    response.data = Some(to_binary(&HandleAnswer::UpdateItem {
+        status: ResponseStatus::Success,
+    })?);
+    Ok(response)
+}
+
+/// The sender's update happens at a random position among the decoys, so the order of the
+/// storage writes doesn't single it out
+fn update_user_quantity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    update_item_data: UpdateItemData,
+) -> StdResult<HandleResponse> {
+    let decoys = update_item_data.decoys.clone().unwrap_or_default();
+    let sender_position = get_sender_position(deps, &env, decoys.len())?;
+    let (category, url) = (
+        update_item_data.category.clone(),
+        update_item_data.url.clone(),
+    );
+
+    touch_decoys(deps, &category, &url, &decoys[..sender_position])?;
+    let response = update_sender_quantity(deps, env, update_item_data)?;
+    touch_decoys(deps, &category, &url, &decoys[sender_position..])?;
+    Ok(response)
+}
+
+fn get_sender_position<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    decoys_count: usize,
+) -> StdResult<usize> {
+    let mut rng_entropy = Vec::new();
+    rng_entropy.extend_from_slice(&env.block.height.to_be_bytes());
+    rng_entropy.extend_from_slice(&env.block.time.to_be_bytes());
+    rng_entropy.extend_from_slice(env.message.sender.0.as_bytes());
+    let mut rng = Prng::new(&load_config(&deps.storage)?.prng_seed, &rng_entropy);
+    let mut random = [0u8; 8];
+    random.copy_from_slice(&rng.rand_bytes()[..8]);
+    Ok((u64::from_be_bytes(random) % (decoys_count as u64 + 1)) as usize)
+}
+
+fn update_sender_quantity<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    update_item_data: UpdateItemData,
 ) -> StdResult<HandleResponse> {
     let (new_quantity, item_data, current_group_size, old_quantity_obj) =
         get_update_data(deps, &env, &update_item_data)?;
+    let user_details = get_participation_details(deps, &env, &update_item_data)?;
 
-    if old_quantity_obj == None {
+    let old_quantity = old_quantity_obj.as_ref().map_or(0, |obj| obj.quantity);
+    if new_quantity > old_quantity
+        && load_config(&deps.storage)?.status == ContractStatus::StopNewListingsAndJoins
+    {
+        return Err(StdError::generic_err(
+            "Joining groups is paused. Quantities can only be reduced",
+        ));
+    }
+    if let Some(deadline) = item_data.deadline {
+        if new_quantity > old_quantity && env.block.time >= deadline {
+            return Err(StdError::generic_err(
+                "The deadline of this item has passed. Quantities can only be reduced",
+            ));
+        }
+    }
+
+    check_deposit(&env, old_quantity, new_quantity, &item_data)?;
+
+    if new_quantity > 0 {
+        validate_contact_data(deps, &user_details)?;
+    }
+
+    if old_quantity_obj.is_none() {
         return update_item_new_user(
             current_group_size,
             &item_data,
             &env,
             &update_item_data,
+            &user_details,
             deps,
         );
     }
 
-    let old_quantity = old_quantity_obj.unwrap().quantity;
-
     // old_quantity is positive
 
     if new_quantity == 0 {
         // old_quantity > 0, new_quantity == 0
 
-        remove_user_from_item(
+        return remove_user_from_item(
             deps,
             current_group_size,
             old_quantity,
             &env,
             &update_item_data,
+            &user_details,
             &item_data,
-        )?;
+        );
     }
 
     // old_quantity >0 , new_quantity > 0
@@ -58,16 +139,42 @@ pub fn update_user_for_item<S: Storage, A: Api, Q: Querier>(
         current_group_size,
         old_quantity,
         &update_item_data,
+        &user_details,
     )?;
+    update_participation_stats(deps, &update_item_data.category, &item_data, |stats| {
+        let units = stats.total_units + new_quantity as u64;
+        stats.total_units = units.saturating_sub(old_quantity as u64);
+        let escrowed =
+            stats.escrowed_volume.u128() + new_quantity as u128 * item_data.wanted_price.u128();
+        stats.escrowed_volume =
+            Uint128(escrowed.saturating_sub(old_quantity as u128 * item_data.wanted_price.u128()));
+    })?;
+    let (action, units) = if new_quantity < old_quantity {
+        (TxAction::DecreaseQuantity, old_quantity - new_quantity)
+    } else {
+        (TxAction::IncreaseQuantity, new_quantity - old_quantity)
+    };
+    if units > 0 {
+        record_tx(
+            deps,
+            &env,
+            &env.message.sender,
+            action,
+            &update_item_data,
+            units,
+            units as u128 * item_data.wanted_price.u128(),
+        )?;
+    }
 
     if new_quantity < old_quantity {
         // refund the user: (the client side should charge the comission for that)
-        return refund_user(old_quantity, &item_data, &env, &update_item_data);
+        return refund_user(old_quantity, &item_data, &env, &user_details);
     }
 
     // If the group size goal was reached, pay the seller and remove the item
     if new_quantity > old_quantity
-        && current_group_size + new_quantity - old_quantity >= item_data.group_size_goal
+        && get_new_group_size(current_group_size, old_quantity, new_quantity)?
+            >= item_data.group_size_goal
     {
         return pay_seller(
             current_group_size,
@@ -88,6 +195,202 @@ pub fn update_user_for_item<S: Storage, A: Api, Q: Querier>(
     })
 }
 
+pub fn emergency_withdraw<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: Env,
+    category: String,
+    url: String,
+) -> StdResult<HandleResponse> {
+    if load_config(&deps.storage)?.status != ContractStatus::StopAll {
+        return Err(StdError::generic_err(
+            "Emergency withdrawals are only allowed while the contract is stopped",
+        ));
+    }
+
+    let (_static_prefix, _dynamic_prefix, dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, &category)?;
+    let url_key = sha_256(base64::encode(url.clone()).as_bytes());
+    let user_details = match get_category_item_user_details(
+        &deps.storage,
+        &dynamic_prefix_users,
+        &url_key,
+        &env.message.sender,
+    )? {
+        Some(user_details) => user_details,
+        None => return Err(StdError::generic_err("You don't participate in this item")),
+    };
+
+    // Leaving the group refunds the whole deposit to the refund address the user gave
+    let update_item_data = UpdateItemData {
+        category,
+        url,
+        user_details: UserItemUpdate {
+            refund_address: user_details.refund_address,
+            contact_data: None,
+            sealed_contact_data: None,
+            quantity: 0,
+        },
+        decoys: None,
+        padding: None,
+    };
+    let mut response = update_user_quantity(deps, env, update_item_data)?;
+    response.data = Some(to_binary(&HandleAnswer::EmergencyWithdraw {
+        status: ResponseStatus::Success,
+    })?);
+    Ok(response)
+}
+
+/// Joining or adding units takes a deposit of exactly their price, while any other update
+/// takes no funds at all
+fn check_deposit(
+    env: &Env,
+    old_quantity: u32,
+    new_quantity: u32,
+    item_data: &crate::msg::StaticItemData,
+) -> StdResult<()> {
+    let added_units = new_quantity.saturating_sub(old_quantity) as u128;
+    let expected_deposit = added_units
+        .checked_mul(item_data.wanted_price.u128())
+        .and_then(|price| price.checked_mul(1000000))
+        .ok_or_else(|| StdError::generic_err("The deposit is too large"))?;
+
+    let mut deposit: u128 = 0;
+    for coin in &env.message.sent_funds {
+        if coin.denom != DENOM {
+            return Err(StdError::generic_err(format!(
+                "Only {} can be deposited",
+                DENOM
+            )));
+        }
+        deposit += coin.amount.u128();
+    }
+    if deposit != expected_deposit {
+        return Err(StdError::generic_err(format!(
+            "The deposit must be exactly {}{}",
+            expected_deposit, DENOM
+        )));
+    }
+    Ok(())
+}
+
+/// The participant is always the sender. Only the refund address may point elsewhere. The
+/// refund address and contact data that aren't sent again are kept from the sender's current
+/// participation
+fn get_participation_details<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    update_item_data: &UpdateItemData,
+) -> StdResult<UserItemDetails> {
+    let update = &update_item_data.user_details;
+    if let Some(refund_address) = &update.refund_address {
+        let canonical_refund_address = deps.api.canonical_address(refund_address)?;
+        if deps.api.human_address(&canonical_refund_address)? != *refund_address {
+            return Err(StdError::generic_err("Invalid refund address"));
+        }
+        if *refund_address == env.contract.address {
+            return Err(StdError::generic_err(
+                "The refund address can't be the contract itself",
+            ));
+        }
+    }
+
+    let (_static_prefix, _dynamic_prefix, dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
+    let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
+    let stored_details = get_category_item_user_details(
+        &deps.storage,
+        &dynamic_prefix_users,
+        &url_key,
+        &env.message.sender,
+    )?;
+    let refund_address = match (&update.refund_address, &stored_details) {
+        (None, Some(stored_details)) => stored_details.refund_address.clone(),
+        _ => update.refund_address.clone(),
+    };
+    // Either kind of contact data replaces both, as they can't be set together
+    let (contact_data, sealed_contact_data) = match (
+        &update.contact_data,
+        &update.sealed_contact_data,
+        stored_details,
+    ) {
+        (None, None, Some(stored_details)) => (
+            stored_details.contact_data,
+            stored_details.sealed_contact_data,
+        ),
+        _ => (
+            update.contact_data.clone(),
+            update.sealed_contact_data.clone(),
+        ),
+    };
+
+    Ok(UserItemDetails {
+        account_address: env.message.sender.clone(),
+        refund_address,
+        contact_data,
+        sealed_contact_data,
+        quantity: update.quantity,
+    })
+}
+
+fn touch_decoys<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    category: &str,
+    url: &str,
+    decoys: &[HumanAddr],
+) -> StdResult<()> {
+    if decoys.is_empty() {
+        return Ok(());
+    }
+    let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, category)?;
+    let url_key = sha_256(base64::encode(url).as_bytes());
+
+    for decoy in decoys {
+        let decoy_canonical_address = deps.api.canonical_address(decoy)?;
+        rewrite_category_item_user_records(
+            &mut deps.storage,
+            &dynamic_prefix,
+            &dynamic_prefix_users,
+            &url_key,
+            decoy_canonical_address.as_slice(),
+            decoy,
+            url,
+        )?;
+    }
+    Ok(())
+}
+
+fn get_refund_address(user_details: &UserItemDetails) -> &HumanAddr {
+    user_details
+        .refund_address
+        .as_ref()
+        .unwrap_or(&user_details.account_address)
+}
+
+fn validate_contact_data<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    user_details: &UserItemDetails,
+) -> StdResult<()> {
+    if user_details.sealed_contact_data.is_some() {
+        if user_details.contact_data.is_some() {
+            return Err(StdError::generic_err(
+                "Contact data can't be given both sealed and in plaintext",
+            ));
+        }
+        return Ok(());
+    }
+
+    let user_canonical_address = deps.api.canonical_address(&user_details.account_address)?;
+    let contact_data =
+        get_participation_contact_data(&deps.storage, &user_canonical_address, user_details)?;
+    if contact_data.is_none() {
+        return Err(StdError::generic_err(
+            "Set a contact profile or provide contact data for this item",
+        ));
+    }
+    Ok(())
+}
+
 fn get_update_data<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
     env: &Env,
@@ -104,10 +407,10 @@ fn get_update_data<S: Storage, A: Api, Q: Querier>(
     let sender_canonical_address = deps.api.canonical_address(&env.message.sender)?;
     let new_quantity = update_item_data.user_details.quantity;
     let (static_prefix, dynamic_prefix, _dynamic_prefix_users) =
-        get_category_prefixes(update_item_data.category.as_bytes())?;
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
     let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
     let item_data =
-        match get_category_item_by_url(&deps.storage, static_prefix, &update_item_data.url)? {
+        match get_category_item_by_url(&deps.storage, &static_prefix, &update_item_data.url)? {
             Some(v) => v,
             None => {
                 return Err(StdError::generic_err(
@@ -116,13 +419,13 @@ fn get_update_data<S: Storage, A: Api, Q: Querier>(
             }
         };
     let current_group_size =
-        match get_category_item_group_size(&deps.storage, dynamic_prefix, &url_key)? {
+        match get_category_item_group_size(&deps.storage, &dynamic_prefix, &url_key)? {
             Some(current_group_size) => current_group_size,
             None => return Err(StdError::generic_err("This item does not exist anymore")),
         };
     let old_quantity_obj = get_category_user_items_quantities_by_url(
         &deps.storage,
-        dynamic_prefix,
+        &dynamic_prefix,
         sender_canonical_address.as_slice(),
         &update_item_data.url,
     )?;
@@ -134,6 +437,19 @@ fn get_update_data<S: Storage, A: Api, Q: Querier>(
     ))
 }
 
+/// The group size after a participant changes their quantity from `old_quantity` to
+/// `new_quantity`. Fails instead of wrapping around if the records of the item don't add up
+fn get_new_group_size(
+    current_group_size: u32,
+    old_quantity: u32,
+    new_quantity: u32,
+) -> StdResult<u32> {
+    current_group_size
+        .checked_sub(old_quantity)
+        .and_then(|group_size| group_size.checked_add(new_quantity))
+        .ok_or_else(|| StdError::generic_err("The group size of this item is inconsistent"))
+}
+
 fn pay_seller<S: Storage, A: Api, Q: Querier>(
     current_group_size: u32,
     old_quantity: u32,
@@ -143,30 +459,164 @@ fn pay_seller<S: Storage, A: Api, Q: Querier>(
     deps: &mut Extern<S, A, Q>,
 ) -> StdResult<HandleResponse> {
     let new_quantity = update_item_data.user_details.quantity;
+    let new_group_size = get_new_group_size(current_group_size, old_quantity, new_quantity)?;
 
-    let seller_payment =
-        (current_group_size + new_quantity - old_quantity) as u128 * item_data.wanted_price.u128();
-    let transfer_funds_msg = transfer_funds(
-        &env.contract.address,
-        &HumanAddr(item_data.seller_address),
+    let group_payment = new_group_size as u128 * item_data.wanted_price.u128();
+    let (transfer_funds_msg, seller_payment) = pay_group(deps, &env, &item_data, group_payment)?;
+    record_tx(
+        deps,
+        &env,
+        &HumanAddr(item_data.seller_address.clone()),
+        TxAction::SellerPayout,
+        &update_item_data,
+        new_group_size,
         seller_payment,
     )?;
+    archive_order(deps, &update_item_data, &item_data, new_group_size)?;
     remove_item_authenticated(&update_item_data.category, &update_item_data.url, deps)?;
+    record_fulfilled_listing(deps, &update_item_data.category, seller_payment)?;
     Ok(transfer_funds_msg)
 }
 
+/// Records a movement of funds of the item in the account's history
+fn record_tx<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    env: &Env,
+    account: &HumanAddr,
+    action: TxAction,
+    update_item_data: &UpdateItemData,
+    quantity: u32,
+    amount: u128,
+) -> StdResult<()> {
+    let canonical_address = deps.api.canonical_address(account)?;
+    let tx = Tx {
+        // Assigned when stored
+        id: 0,
+        action,
+        category: update_item_data.category.clone(),
+        url: update_item_data.url.clone(),
+        quantity,
+        amount: Uint128(amount),
+        block_time: env.block.time,
+    };
+    append_tx(&mut deps.storage, &canonical_address, tx)
+}
+
+fn record_fulfilled_listing<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    category: &str,
+    seller_payment: u128,
+) -> StdResult<()> {
+    update_stats(&mut deps.storage, category, |stats| {
+        stats.fulfilled_listings += 1;
+        stats.paid_out_volume = Uint128(stats.paid_out_volume.u128() + seller_payment);
+    })
+}
+
+/// The public counters would reveal the exact quantity of each update, so the participations in
+/// items with bucketed progress are left out of them
+fn update_participation_stats<S: Storage, A: Api, Q: Querier, F: Fn(&mut MarketStats)>(
+    deps: &mut Extern<S, A, Q>,
+    category: &str,
+    item_data: &crate::msg::StaticItemData,
+    update: F,
+) -> StdResult<()> {
+    if item_data.bucketed_progress {
+        return Ok(());
+    }
+    update_stats(&mut deps.storage, category, update)
+}
+
+/// Keeps the item and its participants for the seller, until the order is shipped
+fn archive_order<S: Storage, A: Api, Q: Querier>(
+    deps: &mut Extern<S, A, Q>,
+    update_item_data: &UpdateItemData,
+    item_data: &crate::msg::StaticItemData,
+    total_quantity: u32,
+) -> StdResult<()> {
+    let (_static_prefix, _dynamic_prefix, dynamic_prefix_users) =
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
+    let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
+    let order_key = order_key(&update_item_data.category, &update_item_data.url);
+
+    let order = Order {
+        static_data: item_data.clone(),
+        total_quantity,
+        shipped_at: None,
+    };
+    save_order(&mut deps.storage, &order_key, &order)?;
+
+    let users_details =
+        get_all_category_item_users_details(&deps.storage, &dynamic_prefix_users, &url_key)?;
+    for user_details in users_details {
+        // The order keeps the contact data as it is when the goal is reached
+        let user_canonical_address = deps.api.canonical_address(&user_details.account_address)?;
+        let contact_data =
+            get_participation_contact_data(&deps.storage, &user_canonical_address, &user_details)?;
+        let participant = OrderParticipant {
+            account_address: user_details.account_address,
+            refund_address: user_details.refund_address,
+            quantity: user_details.quantity,
+            contact_data,
+            sealed_contact_data: user_details.sealed_contact_data,
+        };
+        save_order_participant(&mut deps.storage, &order_key, &participant)?;
+        save_user_order(&mut deps.storage, &user_canonical_address, &order_key)?;
+    }
+    Ok(())
+}
+
+/// Pays the seller for the group, minus the contract's fee. Returns the seller's part
+fn pay_group<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    env: &Env,
+    item_data: &crate::msg::StaticItemData,
+    group_payment: u128,
+) -> StdResult<(HandleResponse, u128)> {
+    let config = load_config(&deps.storage)?;
+    // Computed in uscrt, so that fees below a whole SCRT aren't lost
+    let group_payment = group_payment * 1000000;
+    let fee = group_payment * config.fee_bps as u128 / MAX_FEE_BPS as u128;
+    let seller_payment = group_payment - fee;
+
+    let payout_address = get_seller_payout_address(deps, item_data)?;
+    let mut transfer_funds_msg =
+        transfer_uscrt(&env.contract.address, &payout_address, seller_payment)?;
+    if fee > 0 {
+        let fee_msg = transfer_uscrt(&env.contract.address, &config.fee_recipient, fee)?;
+        transfer_funds_msg.messages.extend(fee_msg.messages);
+    }
+    // The history and the stats are in SCRT
+    Ok((transfer_funds_msg, seller_payment / 1000000))
+}
+
+fn get_seller_payout_address<S: Storage, A: Api, Q: Querier>(
+    deps: &Extern<S, A, Q>,
+    item_data: &crate::msg::StaticItemData,
+) -> StdResult<HumanAddr> {
+    let seller_canonical_address = deps
+        .api
+        .canonical_address(&HumanAddr(item_data.seller_address.clone()))?;
+    match may_load_seller_profile(&deps.storage, &seller_canonical_address)? {
+        Some(profile) => Ok(profile.payout_address),
+        None => Err(StdError::generic_err(
+            "The seller of this item is not registered",
+        )),
+    }
+}
+
 fn refund_user(
     old_quantity: u32,
     item_data: &crate::msg::StaticItemData,
     env: &Env,
-    update_item_data: &UpdateItemData,
+    user_details: &UserItemDetails,
 ) -> StdResult<HandleResponse> {
-    let new_quantity = update_item_data.user_details.quantity;
+    let new_quantity = user_details.quantity;
 
     let refund_amount = (old_quantity - new_quantity) as u128 * item_data.wanted_price.u128();
     let transfer_funds_msg = transfer_funds(
         &env.contract.address,
-        &update_item_data.user_details.account_address,
+        get_refund_address(user_details),
         refund_amount,
     )?;
     Ok(transfer_funds_msg)
@@ -178,37 +628,52 @@ fn remove_user_from_item<S: Storage, A: Api, Q: Querier>(
     old_quantity: u32,
     env: &Env,
     update_item_data: &UpdateItemData,
+    user_details: &UserItemDetails,
     item_data: &crate::msg::StaticItemData,
 ) -> StdResult<HandleResponse> {
     let sender_canonical_address = deps.api.canonical_address(&env.message.sender)?;
 
     // Get the current product count of units: [dynamic_prefix, url]
     let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
-        get_category_prefixes(update_item_data.category.as_bytes())?;
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
     let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
 
     update_current_group_size(
         &mut deps.storage,
         &url_key,
-        dynamic_prefix,
-        current_group_size - old_quantity,
-    )?;
-    remove_category_item_user_details(
-        &mut deps.storage,
-        dynamic_prefix_users,
-        &url_key,
-        &env.message.sender,
+        &dynamic_prefix,
+        get_new_group_size(current_group_size, old_quantity, 0)?,
     )?;
     remove_user_item_quantity(
         &mut deps.storage,
-        dynamic_prefix,
+        &dynamic_prefix,
         sender_canonical_address.as_slice(),
         &update_item_data.url,
     )?;
+    remove_category_item_user_details(
+        &mut deps.storage,
+        &dynamic_prefix_users,
+        &url_key,
+        &env.message.sender,
+    )?;
     let refund_amount = (old_quantity as u128) * item_data.wanted_price.u128();
+    update_participation_stats(deps, &update_item_data.category, item_data, |stats| {
+        stats.total_participants = stats.total_participants.saturating_sub(1);
+        stats.total_units = stats.total_units.saturating_sub(old_quantity as u64);
+        stats.escrowed_volume = Uint128(stats.escrowed_volume.u128().saturating_sub(refund_amount));
+    })?;
+    record_tx(
+        deps,
+        env,
+        &env.message.sender,
+        TxAction::Leave,
+        update_item_data,
+        old_quantity,
+        refund_amount,
+    )?;
     let transfer_funds_msg = transfer_funds(
         &env.contract.address,
-        &update_item_data.user_details.account_address,
+        get_refund_address(user_details),
         refund_amount,
     )?;
     Ok(transfer_funds_msg)
@@ -219,13 +684,14 @@ fn update_item_new_user<S: Storage, A: Api, Q: Querier>(
     item_data: &crate::msg::StaticItemData,
     env: &Env,
     update_item_data: &UpdateItemData,
+    user_details: &UserItemDetails,
     deps: &mut Extern<S, A, Q>,
 ) -> StdResult<HandleResponse> {
     let sender_canonical_address = deps.api.canonical_address(&env.message.sender)?;
 
     // Get the current product count of units: [dynamic_prefix, url]
     let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
-        get_category_prefixes(update_item_data.category.as_bytes())?;
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
     let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
 
     let new_quantity = update_item_data.user_details.quantity;
@@ -234,16 +700,48 @@ fn update_item_new_user<S: Storage, A: Api, Q: Querier>(
             "Cannot join a purchasing group with 0 quantity",
         ));
     }
-    if new_quantity + current_group_size >= item_data.group_size_goal {
-        let seller_payment =
-            (current_group_size + new_quantity) as u128 * item_data.wanted_price.u128();
-        let transfer_funds_msg = transfer_funds(
-            &env.contract.address,
+    let new_group_size = get_new_group_size(current_group_size, 0, new_quantity)?;
+    if new_group_size >= item_data.group_size_goal {
+        let group_payment = new_group_size as u128 * item_data.wanted_price.u128();
+        let (transfer_funds_msg, seller_payment) = pay_group(deps, env, item_data, group_payment)?;
+        record_tx(
+            deps,
+            env,
+            &env.message.sender,
+            TxAction::Join,
+            update_item_data,
+            new_quantity,
+            new_quantity as u128 * item_data.wanted_price.u128(),
+        )?;
+        record_tx(
+            deps,
+            env,
             &HumanAddr(item_data.seller_address.clone()),
+            TxAction::SellerPayout,
+            update_item_data,
+            new_group_size,
             seller_payment,
         )?;
 
+        archive_order(deps, update_item_data, item_data, new_group_size)?;
+        // The user who completed the group isn't in the item's stores yet
+        let contact_data =
+            get_participation_contact_data(&deps.storage, &sender_canonical_address, user_details)?;
+        let order_key = order_key(&update_item_data.category, &update_item_data.url);
+        save_user_order(&mut deps.storage, &sender_canonical_address, &order_key)?;
+        save_order_participant(
+            &mut deps.storage,
+            &order_key,
+            &OrderParticipant {
+                account_address: user_details.account_address.clone(),
+                refund_address: user_details.refund_address.clone(),
+                quantity: new_quantity,
+                contact_data,
+                sealed_contact_data: user_details.sealed_contact_data.clone(),
+            },
+        )?;
         remove_item_authenticated(&update_item_data.category, &update_item_data.url, deps)?;
+        record_fulfilled_listing(deps, &update_item_data.category, seller_payment)?;
 
         return Ok(transfer_funds_msg);
     }
@@ -252,23 +750,34 @@ fn update_item_new_user<S: Storage, A: Api, Q: Querier>(
         url,
         quantity: new_quantity,
     };
+    update_current_group_size(&mut deps.storage, &url_key, &dynamic_prefix, new_group_size)?;
     save_category_element_user(
         &mut deps.storage,
         sender_canonical_address.as_slice(),
-        dynamic_prefix,
+        &dynamic_prefix,
         &user_product_quantity,
     )?;
-    update_current_group_size(
-        &mut deps.storage,
-        &url_key,
-        dynamic_prefix,
-        current_group_size + new_quantity,
-    )?;
     save_category_element_user_item_details(
         &mut deps.storage,
         &url_key,
-        dynamic_prefix_users,
-        &update_item_data.user_details,
+        &dynamic_prefix_users,
+        user_details,
+    )?;
+    update_participation_stats(deps, &update_item_data.category, item_data, |stats| {
+        stats.total_participants += 1;
+        stats.total_units += new_quantity as u64;
+        stats.escrowed_volume = Uint128(
+            stats.escrowed_volume.u128() + new_quantity as u128 * item_data.wanted_price.u128(),
+        );
+    })?;
+    record_tx(
+        deps,
+        env,
+        &env.message.sender,
+        TxAction::Join,
+        update_item_data,
+        new_quantity,
+        new_quantity as u128 * item_data.wanted_price.u128(),
     )?;
     Ok(HandleResponse {
         messages: vec![],
@@ -285,6 +794,7 @@ fn update_item_in_stores<S: Storage, A: Api, Q: Querier>(
     current_group_size: u32,
     old_quantity: u32,
     update_item_data: &UpdateItemData,
+    user_details: &UserItemDetails,
 ) -> Result<(), StdError> {
     let sender_canonical_address = deps.api.canonical_address(&env.message.sender)?;
 
@@ -292,30 +802,39 @@ fn update_item_in_stores<S: Storage, A: Api, Q: Querier>(
 
     // Get the current product count of units: [dynamic_prefix, url]
     let (_static_prefix, dynamic_prefix, dynamic_prefix_users) =
-        get_category_prefixes(update_item_data.category.as_bytes())?;
+        get_category_prefixes(&deps.storage, &update_item_data.category)?;
     let url_key = sha_256(base64::encode(update_item_data.url.clone()).as_bytes());
     update_current_group_size(
         &mut deps.storage,
         &url_key,
-        dynamic_prefix,
-        current_group_size + new_quantity - old_quantity,
-    )?;
-    update_category_item_user_details(
-        &mut deps.storage,
-        dynamic_prefix_users,
-        &url_key,
-        update_item_data,
+        &dynamic_prefix,
+        get_new_group_size(current_group_size, old_quantity, new_quantity)?,
     )?;
     update_user_item_quantity(
         &mut deps.storage,
-        dynamic_prefix,
+        &dynamic_prefix,
         sender_canonical_address.as_slice(),
         update_item_data,
     )?;
+    update_category_item_user_details(
+        &mut deps.storage,
+        &dynamic_prefix_users,
+        &url_key,
+        user_details,
+    )?;
     Ok(())
 }
 
-fn transfer_funds(
+pub(crate) fn transfer_funds(
+    from_address: &HumanAddr,
+    to_address: &HumanAddr,
+    amount: u128,
+) -> StdResult<HandleResponse> {
+    transfer_uscrt(from_address, to_address, amount * 1000000)
+}
+
+/// Like `transfer_funds`, for an amount in uscrt rather than scrt
+pub(crate) fn transfer_uscrt(
     from_address: &HumanAddr,
     to_address: &HumanAddr,
     amount: u128,
@@ -327,8 +846,8 @@ fn transfer_funds(
             from_address,
             to_address,
             amount: vec![Coin {
-                denom: "uscrt".into(),
-                amount: Uint128(amount * 1000000),
+                denom: DENOM.into(),
+                amount: Uint128(amount),
             }],
         })],
         log: vec![],
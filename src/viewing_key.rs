@@ -1,21 +1,66 @@
 use std::fmt;
 
-use cosmwasm_std::ReadonlyStorage;
+use cosmwasm_std::{ReadonlyStorage, StdResult};
 use cosmwasm_storage::{PrefixedStorage, ReadonlyPrefixedStorage};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{CanonicalAddr, Storage};
+use cosmwasm_std::{CanonicalAddr, Env, Storage};
+use secret_toolkit::{
+    crypto::{sha_256, Prng},
+    storage::{TypedStore, TypedStoreMut},
+};
 
 use crate::utils::{create_hashed_password, ct_slice_compare};
 
 pub const VIEWING_KEY_SIZE: usize = 32;
-// pub const VIEWING_KEY_PREFIX: &str = "api_key_";
+pub const VIEWING_KEY_PREFIX: &str = "api_key_";
 pub const PREFIX_VIEWING_KEY: &[u8] = b"viewingkey";
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ViewingKey(pub String);
 
+/// What a viewing key grants access to
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ViewingKeyScope {
+    /// Grants nothing beyond the public catalog
+    Catalog,
+    /// The items the user participates in, and their return requests
+    Participations,
+    /// The user's contact data, along with their participations
+    ContactData,
+    /// The orders of a seller, their participants and return requests. Also needed to remove items
+    SellerOrders,
+}
+
+/// The hash of a viewing key, along with its restrictions
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StoredViewingKey {
+    pub hashed_key: Vec<u8>,
+    /// The block time (in seconds) from which the key is rejected. Queries check it against the
+    /// block time saved by the latest transaction, which may lag behind the current one
+    pub expires_at: Option<u64>,
+    /// All the scopes are granted if not set
+    pub scopes: Option<Vec<ViewingKeyScope>>,
+}
+
+impl StoredViewingKey {
+    pub fn has_scope(&self, scope: ViewingKeyScope) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.contains(&scope),
+            None => true,
+        }
+    }
+
+    pub fn is_expired(&self, block_time: u64) -> bool {
+        match self.expires_at {
+            Some(expires_at) => block_time >= expires_at,
+            None => false,
+        }
+    }
+}
+
 impl ViewingKey {
     pub fn check_viewing_key(&self, hashed_pw: &[u8]) -> bool {
         let mine_hashed = create_hashed_password(&self.0);
@@ -23,23 +68,23 @@ impl ViewingKey {
         ct_slice_compare(&mine_hashed, hashed_pw)
     }
 
-    // pub fn new(env: &Env, seed: &[u8], entropy: &[u8]) -> Self {
-    //     // 16 here represents the lengths in bytes of the block height and time.
-    //     let entropy_len = 16 + env.message.sender.len() + entropy.len();
-    //     let mut rng_entropy = Vec::with_capacity(entropy_len);
-    //     rng_entropy.extend_from_slice(&env.block.height.to_be_bytes());
-    //     rng_entropy.extend_from_slice(&env.block.time.to_be_bytes());
-    //     rng_entropy.extend_from_slice(env.message.sender.0.as_bytes());
-    //     rng_entropy.extend_from_slice(entropy);
+    pub fn new(env: &Env, seed: &[u8], entropy: &[u8]) -> Self {
+        // 16 here represents the lengths in bytes of the block height and time.
+        let entropy_len = 16 + env.message.sender.len() + entropy.len();
+        let mut rng_entropy = Vec::with_capacity(entropy_len);
+        rng_entropy.extend_from_slice(&env.block.height.to_be_bytes());
+        rng_entropy.extend_from_slice(&env.block.time.to_be_bytes());
+        rng_entropy.extend_from_slice(env.message.sender.0.as_bytes());
+        rng_entropy.extend_from_slice(entropy);
 
-    //     let mut rng = Prng::new(seed, &rng_entropy);
+        let mut rng = Prng::new(seed, &rng_entropy);
 
-    //     let rand_slice = rng.rand_bytes();
+        let rand_slice = rng.rand_bytes();
 
-    //     let key = sha_256(&rand_slice);
+        let key = sha_256(&rand_slice);
 
-    //     Self(VIEWING_KEY_PREFIX.to_string() + &base64::encode(key))
-    // }
+        Self(VIEWING_KEY_PREFIX.to_string() + &base64::encode(key))
+    }
 
     pub fn to_hashed(&self) -> [u8; VIEWING_KEY_SIZE] {
         create_hashed_password(&self.0)
@@ -49,14 +94,28 @@ impl ViewingKey {
     //     self.0.as_bytes()
     // }
 
-    pub fn write_viewing_key<S: Storage>(store: &mut S, owner: &CanonicalAddr, key: &ViewingKey) {
+    pub fn write_viewing_key<S: Storage>(
+        store: &mut S,
+        owner: &CanonicalAddr,
+        key: &ViewingKey,
+        expires_at: Option<u64>,
+        scopes: Option<Vec<ViewingKeyScope>>,
+    ) -> StdResult<()> {
         let mut user_key_store = PrefixedStorage::new(PREFIX_VIEWING_KEY, store);
-        user_key_store.set(owner.as_slice(), &key.to_hashed());
+        let stored_key = StoredViewingKey {
+            hashed_key: key.to_hashed().to_vec(),
+            expires_at,
+            scopes,
+        };
+        TypedStoreMut::attach(&mut user_key_store).store(owner.as_slice(), &stored_key)
     }
 
-    pub fn read_viewing_key<S: Storage>(store: &S, owner: &CanonicalAddr) -> Option<Vec<u8>> {
+    pub fn read_viewing_key<S: ReadonlyStorage>(
+        store: &S,
+        owner: &CanonicalAddr,
+    ) -> StdResult<Option<StoredViewingKey>> {
         let user_key_store = ReadonlyPrefixedStorage::new(PREFIX_VIEWING_KEY, store);
-        user_key_store.get(owner.as_slice())
+        TypedStore::attach(&user_key_store).may_load(owner.as_slice())
     }
 }
 